@@ -0,0 +1,365 @@
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Epoch a workbook's date serials count from.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DateSystem {
+    Excel1900,
+    Excel1904,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Date {
+    pub year: i32,
+    pub month: u32,
+    pub day: u32,
+}
+
+impl Date {
+    pub fn new(year: i32, month: u32, day: u32) -> Option<Date> {
+        if !(1..=12).contains(&month) || day < 1 || day > days_in_month(year, month) {
+            return None;
+        }
+        Some(Date { year, month, day })
+    }
+
+    /// Days since 1970-01-01.
+    pub fn to_days(self) -> i64 {
+        // Howard Hinnant's days_from_civil
+        let y = if self.month <= 2 { self.year - 1 } else { self.year } as i64;
+        let era = if y >= 0 { y } else { y - 399 } / 400;
+        let yoe = y - era * 400;
+        let m = self.month as i64;
+        let doy = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + self.day as i64 - 1;
+        let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+        era * 146_097 + doe - 719_468
+    }
+
+    pub fn from_days(days: i64) -> Date {
+        // Howard Hinnant's civil_from_days
+        let z = days + 719_468;
+        let era = if z >= 0 { z } else { z - 146_096 } / 146_097;
+        let doe = z - era * 146_097;
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+        let year = (yoe + era * 400) as i32 + if month <= 2 { 1 } else { 0 };
+        Date { year, month, day }
+    }
+
+    /// Convert an Excel date serial into a date and the number of seconds into that day.
+    pub fn from_excel_serial(serial: f64, system: DateSystem) -> Option<(Date, u32)> {
+        if !serial.is_finite() || serial < 0.0 {
+            return None;
+        }
+        let whole = serial.trunc() as i64;
+        let seconds = ((serial - serial.trunc()) * 86_400.0).round() as u32;
+        let epoch = match system {
+            DateSystem::Excel1904 => Date::new(1904, 1, 1)?.to_days(),
+            // 1900 serials count 1900-02-29, which never existed, so serials after it
+            // are one day further from the epoch than they appear
+            DateSystem::Excel1900 if whole == 60 => return None,
+            DateSystem::Excel1900 if whole > 60 => Date::new(1899, 12, 30)?.to_days(),
+            DateSystem::Excel1900 => Date::new(1899, 12, 31)?.to_days(),
+        };
+        // a time of day that rounds up to midnight belongs to the next day
+        Some((Date::from_days(epoch + whole + (seconds / 86_400) as i64), seconds % 86_400))
+    }
+}
+
+impl Date {
+    /// Today's date in UTC.
+    pub fn today() -> Date {
+        let secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        Date::from_days((secs / 86_400) as i64)
+    }
+
+    /// Parse an ISO-8601 date such as `2020-02-10`.
+    pub fn parse_iso(s: &str) -> Option<Date> {
+        let parts: Vec<&str> = s.trim().split('-').collect();
+        if parts.len() != 3 {
+            return None;
+        }
+        Date::new(
+            parts[0].parse().ok()?,
+            parts[1].parse().ok()?,
+            parts[2].parse().ok()?,
+        )
+    }
+
+    /// Parse a date laid out by a strftime-style format.
+    ///
+    /// Supports `%Y`, `%y` (years 2000-2099), `%m`, `%d`, `%b` and `%B`; any other character
+    /// must appear literally, ignoring case.
+    pub fn parse_format(s: &str, format: &str) -> Option<Date> {
+        let (mut year, mut month, mut day) = (None, None, None);
+        let mut rest = s.trim();
+        let mut fmt_chars = format.chars();
+        while let Some(c) = fmt_chars.next() {
+            if c != '%' {
+                let next = rest.chars().next()?;
+                if !next.eq_ignore_ascii_case(&c) {
+                    return None;
+                }
+                rest = &rest[next.len_utf8()..];
+                continue;
+            }
+            let spec = fmt_chars.next()?;
+            let (max_len, is_digit): (usize, fn(&char) -> bool) = match spec {
+                'Y' => (4, char::is_ascii_digit),
+                'y' | 'm' | 'd' => (2, char::is_ascii_digit),
+                'b' => (3, char::is_ascii_alphabetic),
+                'B' => (usize::MAX, char::is_ascii_alphabetic),
+                _ => return None,
+            };
+            let len = rest.chars().take(max_len).take_while(is_digit).count();
+            if len == 0 {
+                return None;
+            }
+            let (field, remaining) = rest.split_at(len);
+            rest = remaining;
+            match spec {
+                'Y' => year = Some(field.parse().ok()?),
+                'y' => year = Some(2000 + field.parse::<i32>().ok()?),
+                'm' => month = Some(field.parse().ok()?),
+                'd' => day = Some(field.parse().ok()?),
+                _ => month = Some(month_from_name(field)?),
+            }
+        }
+        if !rest.is_empty() {
+            return None;
+        }
+        Date::new(year?, month?, day?)
+    }
+}
+
+impl fmt::Display for Date {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04}-{:02}-{:02}", self.year, self.month, self.day)
+    }
+}
+
+/// An inclusive range of dates, open at either end when a bound is `None`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DateRange {
+    pub from: Option<Date>,
+    pub to: Option<Date>,
+}
+
+impl DateRange {
+    /// Every day of the month given as `YYYY-MM`.
+    pub fn parse_month(s: &str) -> Option<DateRange> {
+        let (year, month) = s.trim().split_once('-')?;
+        let first = Date::new(year.parse().ok()?, month.parse().ok()?, 1)?;
+        Some(DateRange {
+            from: Some(first),
+            to: Date::new(first.year, first.month, days_in_month(first.year, first.month)),
+        })
+    }
+
+    /// The `days` days up to and including `today`.
+    pub fn last(days: i64, today: Date) -> DateRange {
+        DateRange {
+            from: Some(Date::from_days(today.to_days() - days + 1)),
+            to: Some(today),
+        }
+    }
+
+    pub fn contains(&self, date: Date) -> bool {
+        self.from.is_none_or(|from| date >= from) && self.to.is_none_or(|to| date <= to)
+    }
+
+    /// Whether any day of `year` falls in the range.
+    pub fn contains_year(&self, year: i32) -> bool {
+        self.from.is_none_or(|from| year >= from.year) && self.to.is_none_or(|to| year <= to.year)
+    }
+
+    /// Whether any day of `month` in `year` falls in the range.
+    pub fn contains_month(&self, year: i32, month: u32) -> bool {
+        self.from
+            .is_none_or(|from| (year, month) >= (from.year, from.month))
+            && self.to.is_none_or(|to| (year, month) <= (to.year, to.month))
+    }
+}
+
+/// Parse a number of days given as `Nd`, or `Nw` for weeks.
+pub fn parse_day_count(s: &str) -> Option<i64> {
+    let s = s.trim();
+    let days: i64 = if let Some(count) = s.strip_suffix('d') {
+        count.parse().ok()?
+    } else if let Some(count) = s.strip_suffix('w') {
+        count.parse::<i64>().ok()?.checked_mul(7)?
+    } else {
+        return None;
+    };
+    if days < 1 {
+        return None;
+    }
+    Some(days)
+}
+
+const MONTH_NAMES: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+
+/// Month number for a full or three letter month name, ignoring case.
+pub fn month_from_name(name: &str) -> Option<u32> {
+    let name = name.trim().to_lowercase();
+    if name.len() < 3 {
+        return None;
+    }
+    MONTH_NAMES
+        .iter()
+        .position(|m| {
+            let m = m.to_lowercase();
+            m == name || m[..3] == name
+        })
+        .map(|i| i as u32 + 1)
+}
+
+pub fn is_leap_year(year: i32) -> bool {
+    (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+}
+
+pub fn days_in_month(year: i32, month: u32) -> u32 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Render seconds since midnight as `HH:MM:SS`.
+pub fn format_time(seconds: u32) -> String {
+    format!(
+        "{:02}:{:02}:{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: i32, month: u32, day: u32) -> Date {
+        Date::new(year, month, day).unwrap()
+    }
+
+    #[test]
+    fn days_round_trip() {
+        for d in [date(1970, 1, 1), date(1900, 3, 1), date(2020, 2, 29), date(2099, 12, 31)] {
+            assert_eq!(Date::from_days(d.to_days()), d);
+        }
+        assert_eq!(date(1970, 1, 1).to_days(), 0);
+        assert_eq!(date(2020, 3, 1).to_days() - date(2020, 2, 28).to_days(), 2);
+    }
+
+    #[test]
+    fn new_rejects_invalid_dates() {
+        assert!(Date::new(2019, 2, 29).is_none());
+        assert!(Date::new(2020, 13, 1).is_none());
+        assert!(Date::new(2020, 4, 31).is_none());
+        assert!(Date::new(2020, 2, 29).is_some());
+    }
+
+    #[test]
+    fn excel_serials() {
+        let day = |serial, system| Date::from_excel_serial(serial, system).map(|(d, _)| d);
+        assert_eq!(day(1.0, DateSystem::Excel1900), Some(date(1900, 1, 1)));
+        assert_eq!(day(59.0, DateSystem::Excel1900), Some(date(1900, 2, 28)));
+        assert_eq!(day(60.0, DateSystem::Excel1900), None);
+        assert_eq!(day(61.0, DateSystem::Excel1900), Some(date(1900, 3, 1)));
+        assert_eq!(day(43_831.0, DateSystem::Excel1900), Some(date(2020, 1, 1)));
+        assert_eq!(day(42_369.0, DateSystem::Excel1904), Some(date(2020, 1, 1)));
+        assert_eq!(day(-1.0, DateSystem::Excel1900), None);
+        assert_eq!(
+            Date::from_excel_serial(43_831.75, DateSystem::Excel1900),
+            Some((date(2020, 1, 1), 18 * 3600))
+        );
+        // a time that rounds up to midnight is the next day
+        assert_eq!(
+            Date::from_excel_serial(43_831.999_999_9, DateSystem::Excel1900),
+            Some((date(2020, 1, 2), 0))
+        );
+    }
+
+    #[test]
+    fn parse_formats() {
+        assert_eq!(Date::parse_iso(" 2020-02-10 "), Some(date(2020, 2, 10)));
+        assert_eq!(Date::parse_iso("2020-02"), None);
+        assert_eq!(Date::parse_format("17-Mar-2020", "%d-%b-%Y"), Some(date(2020, 3, 17)));
+        assert_eq!(Date::parse_format("17-mar-20", "%d-%b-%y"), Some(date(2020, 3, 17)));
+        assert_eq!(Date::parse_format("March 2020 5", "%B %Y %d"), Some(date(2020, 3, 5)));
+        assert_eq!(Date::parse_format("17-Mar-2020x", "%d-%b-%Y"), None);
+        assert_eq!(Date::parse_format("17-Mzr-2020", "%d-%b-%Y"), None);
+    }
+
+    #[test]
+    fn parse_month_covers_whole_month() {
+        let range = DateRange::parse_month("2020-02").unwrap();
+        assert_eq!((range.from, range.to), (Some(date(2020, 2, 1)), Some(date(2020, 2, 29))));
+        assert!(DateRange::parse_month("2020-13").is_none());
+    }
+
+    #[test]
+    fn last_counts_back_from_today() {
+        let today = date(2020, 3, 2);
+        let range = DateRange::last(3, today);
+        assert_eq!((range.from, range.to), (Some(date(2020, 2, 29)), Some(today)));
+        assert_eq!(DateRange::last(1, today).from, Some(today));
+    }
+
+    #[test]
+    fn day_counts() {
+        assert_eq!(parse_day_count("3d"), Some(3));
+        assert_eq!(parse_day_count(" 2w "), Some(14));
+        assert_eq!(parse_day_count("0d"), None);
+        assert_eq!(parse_day_count("3"), None);
+        assert_eq!(parse_day_count(""), None);
+        assert_eq!(parse_day_count("3é"), None);
+        assert_eq!(parse_day_count("é"), None);
+        assert_eq!(parse_day_count("9223372036854775807w"), None);
+    }
+
+    #[test]
+    fn range_contains() {
+        let range = DateRange {
+            from: Some(date(2020, 2, 15)),
+            to: Some(date(2020, 4, 1)),
+        };
+        assert!(range.contains(date(2020, 2, 15)));
+        assert!(range.contains(date(2020, 4, 1)));
+        assert!(!range.contains(date(2020, 4, 2)));
+        assert!(range.contains_month(2020, 2));
+        assert!(!range.contains_month(2020, 1));
+        assert!(range.contains_year(2020));
+        assert!(!range.contains_year(2021));
+        assert!(DateRange::default().contains(date(1999, 1, 1)));
+    }
+
+    #[test]
+    fn month_names() {
+        assert_eq!(month_from_name("march"), Some(3));
+        assert_eq!(month_from_name("SEP"), Some(9));
+        assert_eq!(month_from_name("Ma"), None);
+        assert_eq!(month_from_name("Marc"), None);
+    }
+}
@@ -0,0 +1,321 @@
+use std::cell::RefCell;
+use std::collections::hash_map;
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::dates::Date;
+
+/// Separates the path of a zip archive from the path of a file inside it, as in
+/// `March.zip!/17-Mar-2020/agricor micro A/cert generator agricor micro A.xlsm`.
+pub const ARCHIVE_SEPARATOR: &str = "!/";
+
+type ZipReader = zip::ZipArchive<BufReader<File>>;
+
+thread_local! {
+    /// Archives opened during this run, so each one's directory is read once however many of
+    /// its files are listed, stamped and read.
+    static OPEN_ARCHIVES: RefCell<HashMap<PathBuf, ZipReader>> = RefCell::new(HashMap::new());
+}
+
+/// Run `f` on the archive at `path`, opening it on first use.
+fn with_archive<T>(path: &Path, f: impl FnOnce(&mut ZipReader) -> io::Result<T>) -> io::Result<T> {
+    OPEN_ARCHIVES.with(|open| {
+        let mut open = open.borrow_mut();
+        let archive = match open.entry(path.to_path_buf()) {
+            hash_map::Entry::Occupied(entry) => entry.into_mut(),
+            hash_map::Entry::Vacant(entry) => {
+                entry.insert(zip::ZipArchive::new(BufReader::new(File::open(path)?))?)
+            }
+        };
+        f(archive)
+    })
+}
+
+/// Close the archives opened so far, so that changes made to them since are seen.
+pub fn close_archives() {
+    OPEN_ARCHIVES.with(|open| open.borrow_mut().clear());
+}
+
+/// The files inside a zip archive.
+pub struct ArchiveListing {
+    path: PathBuf,
+    files: Vec<String>,
+    /// Directory inside the archive that is walked as its contents: the archive's single top
+    /// level directory when that is named like the archive, as when a folder is zipped.
+    root: String,
+}
+
+impl ArchiveListing {
+    fn open(path: &Path) -> Option<Self> {
+        let files: Vec<String> = with_archive(path, |archive| {
+            Ok(archive
+                .file_names()
+                .filter(|n| !n.ends_with('/'))
+                .map(String::from)
+                .collect())
+        })
+        .ok()?;
+        let stem = path.file_stem()?.to_str()?;
+        let stem_dir = format!("{}/", stem);
+        let root = if !files.is_empty() && files.iter().all(|f| f.starts_with(&stem_dir)) {
+            stem.to_string()
+        } else {
+            String::new()
+        };
+        Some(ArchiveListing {
+            path: path.to_path_buf(),
+            files,
+            root,
+        })
+    }
+}
+
+/// A directory or file walked by a layout, either on disk or inside a zip archive.
+///
+/// A zip archive is walked as a directory named like the archive without its extension.
+#[derive(Clone)]
+pub enum Entry {
+    Disk(PathBuf),
+    Archived {
+        listing: Rc<ArchiveListing>,
+        /// Path inside the archive, without a trailing slash.
+        inner: String,
+        is_dir: bool,
+    },
+}
+
+impl Entry {
+    /// Path of the entry, with archived entries written `<archive>!/<inner path>`.
+    pub fn path(&self) -> PathBuf {
+        match self {
+            Entry::Disk(path) => path.clone(),
+            Entry::Archived { listing, inner, .. } if inner == &listing.root => listing.path.clone(),
+            Entry::Archived { listing, inner, .. } => PathBuf::from(format!(
+                "{}{}{}",
+                listing.path.display(),
+                ARCHIVE_SEPARATOR,
+                inner
+            )),
+        }
+    }
+
+    pub fn name(&self) -> Option<String> {
+        match self {
+            Entry::Disk(path) if is_zip(path) => Some(path.file_stem()?.to_str()?.to_string()),
+            Entry::Disk(path) => Some(path.file_name()?.to_str()?.to_string()),
+            Entry::Archived { listing, inner, .. } if inner == &listing.root => {
+                Some(listing.path.file_stem()?.to_str()?.to_string())
+            }
+            Entry::Archived { inner, .. } => inner.rsplit('/').next().map(String::from),
+        }
+    }
+
+    pub fn is_dir(&self) -> bool {
+        match self {
+            Entry::Disk(path) => path.is_dir() || is_zip(path),
+            Entry::Archived { is_dir, .. } => *is_dir,
+        }
+    }
+
+    pub fn is_file(&self) -> bool {
+        match self {
+            Entry::Disk(path) => path.is_file() && !is_zip(path),
+            Entry::Archived { is_dir, .. } => !is_dir,
+        }
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        match self {
+            Entry::Disk(path) => path.is_symlink(),
+            Entry::Archived { .. } => false,
+        }
+    }
+
+    /// Sorted entries of a directory, skipping any that cannot be read.
+    pub fn children(&self) -> Vec<Entry> {
+        match self {
+            Entry::Disk(path) if is_zip(path) => match ArchiveListing::open(path) {
+                Some(listing) => Entry::Archived {
+                    inner: listing.root.clone(),
+                    listing: Rc::new(listing),
+                    is_dir: true,
+                }
+                .children(),
+                None => vec![],
+            },
+            Entry::Disk(path) => {
+                let mut paths: Vec<PathBuf> = match fs::read_dir(path) {
+                    Ok(entries) => entries.filter_map(Result::ok).map(|e| e.path()).collect(),
+                    Err(_) => vec![],
+                };
+                paths.sort();
+                paths.into_iter().map(Entry::Disk).collect()
+            }
+            Entry::Archived { listing, inner, .. } => {
+                let prefix = if inner.is_empty() {
+                    String::new()
+                } else {
+                    format!("{}/", inner)
+                };
+                // (name, whether more of the path follows it)
+                let children: BTreeSet<(&str, bool)> = listing
+                    .files
+                    .iter()
+                    .filter_map(|f| f.strip_prefix(&prefix))
+                    .map(|rest| match rest.split_once('/') {
+                        Some((dir, _)) => (dir, true),
+                        None => (rest, false),
+                    })
+                    .collect();
+                children
+                    .into_iter()
+                    .map(|(name, is_dir)| Entry::Archived {
+                        listing: listing.clone(),
+                        inner: format!("{}{}", prefix, name),
+                        is_dir,
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+fn is_zip(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| e.eq_ignore_ascii_case("zip"))
+}
+
+/// Split a path written `<archive>!/<inner path>` into the archive and the inner path.
+///
+/// Only a separator following the path of an existing zip file splits the path, so a
+/// directory whose name contains `!` is not mistaken for an archive.
+pub fn split_archive_path(path: &Path) -> Option<(PathBuf, &str)> {
+    let s = path.to_str()?;
+    s.match_indices(ARCHIVE_SEPARATOR).find_map(|(idx, separator)| {
+        let archive_path = Path::new(&s[..idx]);
+        if is_zip(archive_path) && archive_path.is_file() {
+            Some((archive_path.to_path_buf(), &s[idx + separator.len()..]))
+        } else {
+            None
+        }
+    })
+}
+
+/// Read a whole file, which may be inside a zip archive.
+pub fn read_cg_file(path: &Path) -> io::Result<Vec<u8>> {
+    let (archive_path, inner) = match split_archive_path(path) {
+        Some(split) => split,
+        None => return fs::read(path),
+    };
+    with_archive(&archive_path, |archive| {
+        let mut file = archive.by_name(inner)?;
+        let mut bytes = Vec::with_capacity(file.size() as usize);
+        file.read_to_end(&mut bytes)?;
+        Ok(bytes)
+    })
+}
+
+/// Whether a file exists, which may be inside a zip archive.
+pub fn cg_file_exists(path: &Path) -> bool {
+    match split_archive_path(path) {
+        Some((archive_path, inner)) => {
+            with_archive(&archive_path, |archive| Ok(archive.by_name(inner).is_ok()))
+                .unwrap_or(false)
+        }
+        None => path.is_file(),
+    }
+}
+
+/// Size and modification time, in seconds since the Unix epoch, of a file which may be inside
+/// a zip archive.
+pub fn cg_file_stamp(path: &Path) -> io::Result<(u64, u64)> {
+    if let Some((archive_path, inner)) = split_archive_path(path) {
+        return with_archive(&archive_path, |archive| {
+            let file = archive.by_name(inner)?;
+            Ok((file.size(), zip_time_secs(file.last_modified())))
+        });
+    }
+    let metadata = fs::metadata(path)?;
+    let modified = metadata
+        .modified()?
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    Ok((metadata.len(), modified))
+}
+
+/// Seconds since the Unix epoch of a zip entry's modification time, which has no time zone.
+fn zip_time_secs(time: zip::DateTime) -> u64 {
+    let date = Date::new(time.year() as i32, time.month() as u32, time.day() as u32);
+    let secs_into_day =
+        time.hour() as u64 * 3600 + time.minute() as u64 * 60 + time.second() as u64;
+    date.map_or(0, |d| d.to_days().max(0) as u64 * 86_400 + secs_into_day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    use crate::test_utils::temp_dir;
+
+    fn write_zip(path: &Path, files: &[(&str, &[u8])]) {
+        let mut zip = zip::ZipWriter::new(File::create(path).unwrap());
+        for (name, contents) in files.iter() {
+            zip.start_file(*name, Default::default()).unwrap();
+            zip.write_all(contents).unwrap();
+        }
+        zip.finish().unwrap();
+    }
+
+    #[test]
+    fn only_existing_zip_files_are_split() {
+        let dir = temp_dir("archive_split");
+        let archive = dir.join("March.zip");
+        write_zip(&archive, &[("A/cert.xlsm", b"a")]);
+        let archived = PathBuf::from(format!("{}!/A/cert.xlsm", archive.display()));
+        assert_eq!(split_archive_path(&archived), Some((archive.clone(), "A/cert.xlsm")));
+
+        // a directory named like an archive, and a name containing the separator
+        fs::create_dir(dir.join("Old.zip")).unwrap();
+        let in_dir = PathBuf::from(format!("{}!/A/cert.xlsm", dir.join("Old.zip").display()));
+        assert_eq!(split_archive_path(&in_dir), None);
+        let bang = dir.join("Wow!/cert.xlsm");
+        assert_eq!(split_archive_path(&bang), None);
+        let missing = PathBuf::from(format!("{}!/cert.xlsm", dir.join("Gone.zip").display()));
+        assert_eq!(split_archive_path(&missing), None);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn archived_files_are_read_and_walked() {
+        let dir = temp_dir("archive_read");
+        let archive = dir.join("March.zip");
+        write_zip(&archive, &[("March/17/cert A.xlsm", b"first"), ("March/18/cert B.xlsm", b"b")]);
+        let inner = |name: &str| PathBuf::from(format!("{}!/{}", archive.display(), name));
+
+        assert_eq!(read_cg_file(&inner("March/17/cert A.xlsm")).unwrap(), b"first");
+        assert!(cg_file_exists(&inner("March/18/cert B.xlsm")));
+        assert!(!cg_file_exists(&inner("March/18/cert C.xlsm")));
+        assert_eq!(cg_file_stamp(&inner("March/18/cert B.xlsm")).unwrap().0, 1);
+
+        let days: Vec<PathBuf> = Entry::Disk(archive.clone())
+            .children()
+            .iter()
+            .map(Entry::path)
+            .collect();
+        assert_eq!(days, [inner("March/17"), inner("March/18")]);
+
+        // a rewritten archive is read afresh once the open ones are closed
+        write_zip(&archive, &[("March/17/cert A.xlsm", b"second")]);
+        close_archives();
+        assert_eq!(read_cg_file(&inner("March/17/cert A.xlsm")).unwrap(), b"second");
+        close_archives();
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
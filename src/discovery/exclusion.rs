@@ -0,0 +1,179 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use glob::{MatchOptions, Pattern};
+
+use super::{dir_name, CgFile};
+
+/// File names that are never extracted unless `--no-default-excludes` is given: Excel owner
+/// files, copies made by Windows and Excel, and backups.
+pub const DEFAULT_EXCLUDES: &[&str] = &[
+    "~$*",
+    "Copy of *",
+    "* - Copy.*",
+    "* ([0-9]).*",
+    "* ([0-9][0-9]).*",
+    "* old.*",
+    "*backup*",
+];
+
+/// Glob patterns matched against file names, ignoring case, to leave files out of a run.
+#[derive(Default)]
+pub struct Exclusions {
+    patterns: Vec<Pattern>,
+}
+
+impl Exclusions {
+    pub fn new(patterns: &[String]) -> Result<Self, String> {
+        let patterns = patterns
+            .iter()
+            .map(|p| Pattern::new(p).map_err(|e| format!("Invalid exclude pattern '{}': {}", p, e)))
+            .collect::<Result<_, _>>()?;
+        Ok(Exclusions { patterns })
+    }
+
+    /// The first pattern matching the name of `path`.
+    pub fn matching(&self, path: &Path) -> Option<&str> {
+        let name = dir_name(path)?;
+        let options = MatchOptions {
+            case_sensitive: false,
+            ..MatchOptions::new()
+        };
+        self.patterns
+            .iter()
+            .find(|p| p.matches_with(name, options))
+            .map(|p| p.as_str())
+    }
+
+    /// Split `files` into those to extract and those excluded, with the reason for each.
+    pub fn apply(&self, files: Vec<CgFile>) -> (Vec<CgFile>, Vec<(PathBuf, String)>) {
+        let mut kept = vec![];
+        let mut excluded = vec![];
+        for file in files {
+            match self.matching(&file.path) {
+                Some(pattern) => excluded.push((file.path, format!("matches '{}'", pattern))),
+                None => kept.push(file),
+            }
+        }
+        (kept, excluded)
+    }
+}
+
+/// Whether a Test ID stands for a missing one: empty, or the "NA" written for a column that
+/// could not be found.
+fn is_placeholder_id(test_id: &str) -> bool {
+    let test_id = test_id.trim();
+    test_id.is_empty() || test_id.eq_ignore_ascii_case("NA")
+}
+
+/// Find rows whose sample is also held by a newer file in the same batch folder, as when a
+/// batch folder holds several saves of the same workbook.
+///
+/// `rows[i]` is the file a row was read from and the row's Test ID. Rows without a Test ID are
+/// never duplicates, and neither are rows of the same file. Returns the index of each duplicate
+/// row with the file kept in its place: the most recently modified one by `modified`.
+pub fn find_duplicate_rows<'a>(
+    rows: &[(&'a Path, Option<&str>)],
+    modified: impl Fn(&Path) -> Option<u64>,
+) -> Vec<(usize, &'a Path)> {
+    let mut modified_times: HashMap<&Path, Option<u64>> = HashMap::new();
+    for (file, _) in rows.iter() {
+        modified_times.entry(file).or_insert_with(|| modified(file));
+    }
+    let newest = |a: &'a Path, b: &'a Path| {
+        // the later path breaks a tie, so the result does not depend on the order of the rows
+        std::cmp::max_by_key(a, b, |f| (modified_times[f], *f))
+    };
+
+    let mut kept: HashMap<(Option<&Path>, &str), &'a Path> = HashMap::new();
+    for (file, test_id) in rows.iter() {
+        if let Some(test_id) = test_id.filter(|id| !is_placeholder_id(id)) {
+            kept.entry((file.parent(), test_id.trim()))
+                .and_modify(|k| *k = newest(k, file))
+                .or_insert(file);
+        }
+    }
+
+    rows.iter()
+        .enumerate()
+        .filter_map(|(i, (file, test_id))| {
+            let test_id = test_id.filter(|id| !is_placeholder_id(id))?;
+            let kept_file = kept[&(file.parent(), test_id.trim())];
+            if kept_file == *file {
+                None
+            } else {
+                Some((i, kept_file))
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_excludes() {
+        let exclusions = Exclusions::new(
+            &DEFAULT_EXCLUDES.iter().map(|p| p.to_string()).collect::<Vec<_>>(),
+        )
+        .unwrap();
+        let excluded = |name: &str| exclusions.matching(Path::new(name)).is_some();
+        assert!(excluded("~$cert generator agricor micro A.xlsm"));
+        assert!(excluded("Copy of cert generator agricor micro A.xlsm"));
+        assert!(excluded("cert generator agricor micro A - Copy.xlsm"));
+        assert!(excluded("cert generator agricor micro A (2).xlsm"));
+        assert!(excluded("cert generator agricor micro A OLD.xlsm"));
+        assert!(!excluded("cert generator agricor micro A.xlsm"));
+    }
+
+    /// Indexes of the duplicate rows, with files modified at the times given in their names.
+    fn duplicates(rows: &[(&str, Option<&str>)]) -> Vec<(usize, PathBuf)> {
+        let rows: Vec<(&Path, Option<&str>)> =
+            rows.iter().map(|(f, id)| (Path::new(*f), *id)).collect();
+        let modified = |f: &Path| f.file_stem()?.to_str()?.rsplit(' ').next()?.parse().ok();
+        find_duplicate_rows(&rows, modified)
+            .into_iter()
+            .map(|(i, kept)| (i, kept.to_path_buf()))
+            .collect()
+    }
+
+    #[test]
+    fn newest_save_keeps_shared_samples() {
+        let rows = [
+            ("A/save 1.xlsm", Some("T-1")),
+            ("A/save 1.xlsm", Some("T-2")),
+            ("A/save 2.xlsm", Some("T-2")),
+            ("A/save 2.xlsm", Some("T-3")),
+        ];
+        // T-1 is only in the older save and is kept
+        assert_eq!(duplicates(&rows), [(1, PathBuf::from("A/save 2.xlsm"))]);
+    }
+
+    #[test]
+    fn placeholder_ids_are_never_duplicates() {
+        let rows = [
+            ("A/save 1.xlsm", Some("NA")),
+            ("A/save 2.xlsm", Some("NA")),
+            ("A/save 1.xlsm", Some(" ")),
+            ("A/save 2.xlsm", None),
+        ];
+        assert!(duplicates(&rows).is_empty());
+    }
+
+    #[test]
+    fn other_folders_and_same_file_rows_are_kept() {
+        let rows = [
+            ("A/save 1.xlsm", Some("T-1")),
+            ("B/save 2.xlsm", Some("T-1")),
+            ("B/save 2.xlsm", Some("T-1")),
+        ];
+        assert!(duplicates(&rows).is_empty());
+    }
+
+    #[test]
+    fn unknown_modification_time_is_oldest() {
+        let rows = [("A/save x.xlsm", Some("T-1")), ("A/save 1.xlsm", Some("T-1"))];
+        assert_eq!(duplicates(&rows), [(0, PathBuf::from("A/save 1.xlsm"))]);
+    }
+}
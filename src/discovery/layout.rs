@@ -0,0 +1,288 @@
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+use glob::{MatchOptions, Pattern};
+
+use super::{parse_year_dir, CgFile, Entry, MonthDirStyle, TestTypeRegex};
+use crate::dates::{Date, DateRange};
+
+/// The archive layout used by the Botanacor and Agricor cert generator folders.
+pub const DEFAULT_LAYOUT: &str = "{year}/{month}/{day:%d-%b-%Y}/{folder}/{file}";
+
+enum Segment {
+    /// `{year}`: a directory named `YYYY`
+    Year,
+    /// `{month}`: a month directory named in the archive's `MonthDirStyle`
+    Month,
+    /// `{day:<format>}`: a directory named by a date in a strftime-style format
+    Day(String),
+    /// `{folder}`: the test type's batch folder
+    Folder,
+    /// `{file}`: the cert generator workbook
+    File,
+    /// `**`: any number of directories, including none
+    AnyDepth,
+    /// Anything else: a directory name, which may contain glob wildcards
+    Literal(Pattern),
+}
+
+/// A template describing where cert generator files are stored below the root directory,
+/// e.g. `{year}/{day:%Y-%m-%d}/{file}` or `**/{file}`.
+pub struct Layout {
+    /// The template the layout was parsed from.
+    template: String,
+    segments: Vec<Segment>,
+}
+
+impl FromStr for Layout {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = s.trim_matches('/').split('/').collect();
+        let mut segments = vec![];
+        for (i, part) in parts.iter().enumerate() {
+            let segment = match *part {
+                "{year}" => Segment::Year,
+                "{month}" => Segment::Month,
+                "{folder}" => Segment::Folder,
+                "{file}" if i == parts.len() - 1 => Segment::File,
+                "{file}" => return Err(format!("{{file}} must end the layout '{}'", s)),
+                "**" => Segment::AnyDepth,
+                _ if part.starts_with("{day:") && part.ends_with('}') => {
+                    Segment::Day(part["{day:".len()..part.len() - 1].to_string())
+                }
+                _ if part.starts_with('{') => {
+                    return Err(format!("Unknown placeholder {} in layout '{}'", part, s))
+                }
+                _ => Segment::Literal(
+                    Pattern::new(part).map_err(|e| format!("Invalid pattern '{}': {}", part, e))?,
+                ),
+            };
+            segments.push(segment);
+        }
+        match segments.last() {
+            Some(Segment::File) => Ok(Layout {
+                template: s.to_string(),
+                segments,
+            }),
+            _ => Err(format!("Layout '{}' must end with {{file}}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Layout {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.template)
+    }
+}
+
+/// What is known about a file from the directories walked to reach it.
+#[derive(Clone, Default)]
+struct Captures {
+    year: Option<i32>,
+    date: Option<Date>,
+    batch: Option<String>,
+}
+
+/// Which files a layout walk keeps.
+pub struct Filters<'a> {
+    pub test_type: &'a TestTypeRegex,
+    pub date_range: &'a DateRange,
+    pub month_dir_style: MonthDirStyle,
+}
+
+impl Layout {
+    /// Whether the layout names the day directories, without which a date range cannot be
+    /// applied to the files it finds.
+    pub fn has_day(&self) -> bool {
+        self.segments.iter().any(|s| matches!(s, Segment::Day(_)))
+    }
+
+    /// Find every file below `root` laid out as this template describes and passing `filters`.
+    ///
+    /// The date range is checked against whichever of year, month and day the layout captures.
+    /// Zip archives are searched as if they were directories named like the archive. `**` does
+    /// not descend into symbolic links, which could otherwise lead it round in a cycle.
+    pub fn find_files(&self, root: &Path, filters: &Filters) -> Vec<CgFile> {
+        let mut found = vec![];
+        let root = Entry::Disk(root.to_path_buf());
+        self.walk(&root, 0, &Captures::default(), filters, &mut found);
+        found
+    }
+
+    fn walk(
+        &self,
+        dir: &Entry,
+        seg_idx: usize,
+        captures: &Captures,
+        filters: &Filters,
+        found: &mut Vec<CgFile>,
+    ) {
+        let segment = &self.segments[seg_idx];
+        if let Segment::AnyDepth = segment {
+            self.walk(dir, seg_idx + 1, captures, filters, found);
+            for child in dir.children().iter().filter(|e| e.is_dir() && !e.is_symlink()) {
+                self.walk(child, seg_idx, captures, filters, found);
+            }
+            return;
+        }
+
+        for child in dir.children() {
+            let name = match child.name() {
+                Some(name) => name,
+                None => continue,
+            };
+            let mut captures = captures.clone();
+            if !segment_matches(segment, &name, &mut captures, filters) {
+                continue;
+            }
+            if seg_idx + 1 == self.segments.len() {
+                if child.is_file() {
+                    found.push(CgFile {
+                        path: child.path(),
+                        run_date: captures.date,
+                        batch: captures.batch,
+                    });
+                }
+            } else if child.is_dir() {
+                self.walk(&child, seg_idx + 1, &captures, filters, found);
+            }
+        }
+    }
+}
+
+/// Check a directory or file name against a layout segment, recording what it reveals.
+fn segment_matches(
+    segment: &Segment,
+    name: &str,
+    captures: &mut Captures,
+    filters: &Filters,
+) -> bool {
+    let date_range = filters.date_range;
+    match segment {
+        Segment::Year => match parse_year_dir(name) {
+            Some(year) if date_range.contains_year(year) => {
+                captures.year = Some(year);
+                true
+            }
+            _ => false,
+        },
+        Segment::Month => match filters.month_dir_style.parse_month(name) {
+            Some(month) => captures
+                .year
+                .is_none_or(|year| date_range.contains_month(year, month)),
+            None => false,
+        },
+        Segment::Day(format) => match Date::parse_format(name, format) {
+            Some(date) if date_range.contains(date) => {
+                captures.date = Some(date);
+                true
+            }
+            _ => false,
+        },
+        Segment::Folder => {
+            captures.batch = Some(name.to_string());
+            filters.test_type.folder.is_match(name)
+        }
+        Segment::File => filters.test_type.file.is_match(name),
+        Segment::Literal(pattern) => pattern.matches_with(
+            name,
+            MatchOptions {
+                case_sensitive: false,
+                ..MatchOptions::new()
+            },
+        ),
+        Segment::AnyDepth => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    use crate::discovery::get_regex;
+    use crate::test_utils::temp_dir;
+
+    fn parse(s: &str) -> Result<Layout, String> {
+        s.parse()
+    }
+
+    #[test]
+    fn parse_layouts() {
+        let layout = parse(DEFAULT_LAYOUT).unwrap();
+        assert!(matches!(
+            layout.segments[..],
+            [Segment::Year, Segment::Month, Segment::Day(ref f), Segment::Folder, Segment::File]
+                if f == "%d-%b-%Y"
+        ));
+        assert!(layout.has_day());
+
+        let layout = parse("/Certs*/**/{file}/").unwrap();
+        assert!(matches!(
+            layout.segments[..],
+            [Segment::Literal(_), Segment::AnyDepth, Segment::File]
+        ));
+        assert!(!layout.has_day());
+        assert!(!parse("{year}/{month}/{file}").unwrap().has_day());
+    }
+
+    #[test]
+    fn invalid_layouts() {
+        assert!(parse("{year}/{folder}").is_err());
+        assert!(parse("{file}/{folder}").is_err());
+        assert!(parse("{week}/{file}").is_err());
+        assert!(parse("[a/{file}").is_err());
+    }
+
+    fn touch(root: &Path, rel: &str) {
+        let path = root.join(rel);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, b"").unwrap();
+    }
+
+    fn find(root: &Path, layout: &str, date_range: &DateRange) -> Vec<String> {
+        let test_type = get_regex("agricor_micro").unwrap().unwrap();
+        let filters = Filters {
+            test_type: &test_type,
+            date_range,
+            month_dir_style: MonthDirStyle::Any,
+        };
+        let mut found: Vec<String> = parse(layout)
+            .unwrap()
+            .find_files(root, &filters)
+            .iter()
+            .map(|f| f.path.strip_prefix(root).unwrap().display().to_string())
+            .collect();
+        found.sort();
+        found
+    }
+
+    #[test]
+    fn dates_filter_the_walk() {
+        let root = temp_dir("layout_dates");
+        let march = "2020/March/17-Mar-2020/agricor micro A/cert generator agricor micro A.xlsm";
+        touch(&root, march);
+        touch(&root, "2020/4/01-Apr-2020/agricor micro B/cert generator agricor micro B.xlsx");
+        touch(&root, "2020/4/01-Apr-2020/agricor potency C/cert generator agricor potency C.xlsm");
+        touch(&root, "2019/12/20-Dec-2019/agricor micro D/cert generator agricor micro D.xlsm");
+
+        let all = find(&root, DEFAULT_LAYOUT, &DateRange::default());
+        assert_eq!(all.len(), 3);
+        let in_march = find(&root, DEFAULT_LAYOUT, &DateRange::parse_month("2020-03").unwrap());
+        assert_eq!(in_march, [march]);
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn any_depth_does_not_follow_symlinks() {
+        let root = temp_dir("layout_symlinks");
+        touch(&root, "a/b/cert generator agricor micro A.xlsm");
+        std::os::unix::fs::symlink(&root, root.join("a/b/loop")).unwrap();
+        let found = find(&root, "**/{file}", &DateRange::default());
+        assert_eq!(found, ["a/b/cert generator agricor micro A.xlsm"]);
+        fs::remove_dir_all(root).unwrap();
+    }
+}
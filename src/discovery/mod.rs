@@ -0,0 +1,312 @@
+mod archive;
+mod exclusion;
+mod layout;
+
+use std::fs;
+use std::io;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use regex::{Regex, RegexBuilder};
+
+use crate::dates::{month_from_name, Date};
+use crate::extractors::extractor_names;
+
+pub use archive::*;
+pub use exclusion::*;
+pub use layout::*;
+
+/// A cert generator workbook found on disk, with the metadata its location implies.
+#[derive(Clone)]
+pub struct CgFile {
+    pub path: PathBuf,
+    /// Date parsed from the day directory the file sits under.
+    pub run_date: Option<Date>,
+    /// Name of the batch folder holding the workbook.
+    pub batch: Option<String>,
+}
+
+impl CgFile {
+    /// Describe a workbook that was listed rather than found by walking a layout.
+    ///
+    /// The batch is its folder if that is named like the test type's batch folders, and the
+    /// run date comes from the nearest enclosing directory named like a date.
+    pub fn from_listed_path(path: PathBuf, regex_struct: &TestTypeRegex) -> Self {
+        let batch = path
+            .parent()
+            .and_then(dir_name)
+            .filter(|name| regex_struct.folder.is_match(name))
+            .map(String::from);
+        let run_date = path
+            .ancestors()
+            .skip(1)
+            .filter_map(dir_name)
+            .find_map(|name| {
+                Date::parse_format(name, "%d-%b-%Y")
+                    .or_else(|| Date::parse_format(name, "%Y-%m-%d"))
+            });
+        CgFile {
+            path,
+            run_date,
+            batch,
+        }
+    }
+}
+
+/// Read a list of workbook paths, one per line, from a file or from stdin when `source` is "-".
+pub fn read_file_list(source: &str) -> io::Result<Vec<PathBuf>> {
+    let mut contents = String::new();
+    if source == "-" {
+        io::stdin().read_to_string(&mut contents)?;
+    } else {
+        contents = fs::read_to_string(source)?;
+    }
+    Ok(contents
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .map(PathBuf::from)
+        .collect())
+}
+
+/// Whether Excel has the workbook at `path` open, which it marks with an owner file named
+/// `~$` followed by the workbook's name, or by its name without the first two characters.
+pub fn has_lock_file(path: &Path) -> bool {
+    let (dir, name) = match (path.parent(), dir_name(path)) {
+        (Some(dir), Some(name)) => (dir, name),
+        _ => return false,
+    };
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return false,
+    };
+    entries.filter_map(Result::ok).any(|e| {
+        e.file_name()
+            .to_str()
+            .and_then(|n| n.strip_prefix("~$"))
+            .is_some_and(|owner| owner == name || name.get(2..) == Some(owner))
+    })
+}
+
+/// Find the test type whose cert generator file pattern matches the name of `path`, among the
+/// test types with a registered extractor.
+pub fn detect_test_type(path: &Path) -> Option<(&'static str, TestTypeRegex)> {
+    let name = dir_name(path)?;
+    extractor_names().find_map(|test_type| match get_regex(test_type) {
+        Some(Ok(regex)) if regex.file.is_match(name) => Some((test_type, regex)),
+        _ => None,
+    })
+}
+
+fn dir_name(path: &Path) -> Option<&str> {
+    path.file_name().and_then(|n| n.to_str())
+}
+
+/// How the month directories of an archive are named.
+#[derive(Clone, Copy, Debug)]
+pub enum MonthDirStyle {
+    /// `3` or `03`
+    Number,
+    /// `03 - March`
+    NumberName,
+    /// `March` or `Mar`
+    Name,
+    /// Any of the above
+    Any,
+}
+
+impl MonthDirStyle {
+    /// The month a directory name denotes, if it is named in this style.
+    pub fn parse_month(self, name: &str) -> Option<u32> {
+        let name = name.trim();
+        match self {
+            MonthDirStyle::Number => {
+                let month = name.parse().ok().filter(|_| name.len() <= 2)?;
+                Some(month).filter(|m| (1..=12).contains(m))
+            }
+            MonthDirStyle::NumberName => {
+                let (number, month_name) = name.split_once('-')?;
+                let month = MonthDirStyle::Number.parse_month(number)?;
+                Some(month).filter(|m| month_from_name(month_name) == Some(*m))
+            }
+            MonthDirStyle::Name => month_from_name(name),
+            MonthDirStyle::Any => MonthDirStyle::Number
+                .parse_month(name)
+                .or_else(|| MonthDirStyle::NumberName.parse_month(name))
+                .or_else(|| MonthDirStyle::Name.parse_month(name)),
+        }
+    }
+}
+
+impl FromStr for MonthDirStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "number" => Ok(MonthDirStyle::Number),
+            "number-name" => Ok(MonthDirStyle::NumberName),
+            "name" => Ok(MonthDirStyle::Name),
+            "any" => Ok(MonthDirStyle::Any),
+            _ => Err(format!(
+                "Unknown month directory style '{}', expected number, number-name, name or any",
+                s
+            )),
+        }
+    }
+}
+
+/// The year a directory named exactly `YYYY` denotes.
+fn parse_year_dir(name: &str) -> Option<i32> {
+    if name.len() == 4 && name.chars().all(|c| c.is_ascii_digit()) {
+        name.parse().ok()
+    } else {
+        None
+    }
+}
+
+pub struct TestTypeRegex {
+    pub folder: Regex,
+    pub file: Regex,
+}
+
+impl TestTypeRegex {
+    fn new(folder_regex: &str, file_regex: &str) -> Result<Self, regex::Error> {
+        Ok(TestTypeRegex {
+            folder: RegexBuilder::new(folder_regex)
+                .case_insensitive(true)
+                .build()?,
+            file: RegexBuilder::new(file_regex)
+                .case_insensitive(true)
+                .build()?,
+        })
+    }
+}
+
+pub fn get_regex(test_type: &str) -> Option<Result<TestTypeRegex, regex::Error>> {
+    match test_type {
+        // Botanacor files
+        "botanacor_potency" => Some(TestTypeRegex::new(
+            r"^botanacor potency ",
+            r"^cert generator botanacor potency .*\.xls[xmb]?$",
+        )),
+        "botanacor_pesticides" => Some(TestTypeRegex::new(
+            r"^botanacor pesticides ",
+            r"^cert generator botanacor pesticides .*\.xls[xmb]?$",
+        )),
+        "botanacor_metals" => Some(TestTypeRegex::new(
+            r"^botanacor metals ",
+            r"^cert generator botanacor metals .*\.xls[xmb]?$",
+        )),
+        "botanacor_micro" => Some(TestTypeRegex::new(
+            "^validated botanacor micro ",
+            r"^cert generator botanacor micro .*\.xls[xmb]?$",
+        )),
+
+        // Agricor files
+        "agricor_micro" => Some(TestTypeRegex::new(
+            "^agricor micro ",
+            r"^cert generator agricor micro .*\.xls[xmb]?$",
+        )),
+        "agricor_potency" => Some(TestTypeRegex::new(
+            "^agricor potency ",
+            r"^cert generator agricor potency .*\.xls[xmb]?$",
+        )),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_extractor_has_a_file_pattern() {
+        for name in extractor_names() {
+            assert!(matches!(get_regex(name), Some(Ok(_))), "{}", name);
+        }
+    }
+
+    #[test]
+    fn only_test_types_with_an_extractor_are_detected() {
+        let detect = |name: &str| detect_test_type(Path::new(name)).map(|(t, _)| t);
+        assert_eq!(detect("cert generator agricor potency X.xlsm"), Some("agricor_potency"));
+        assert_eq!(
+            detect("cert generator botanacor pesticides 1.xlsx"),
+            Some("botanacor_pesticides")
+        );
+        assert_eq!(detect("cert generator botanacor potency 1.xlsm"), None);
+        assert_eq!(detect("cert generator botanacor metals 1.xlsm"), None);
+        assert_eq!(detect("notes.xlsx"), None);
+    }
+
+    #[test]
+    fn number_month_dirs() {
+        let parse = |name| MonthDirStyle::Number.parse_month(name);
+        assert_eq!(parse("3"), Some(3));
+        assert_eq!(parse("03"), Some(3));
+        assert_eq!(parse("12"), Some(12));
+        // near misses of month 1
+        assert_eq!(parse("10"), Some(10));
+        assert_eq!(parse("11"), Some(11));
+        assert_eq!(parse("1 - January"), None);
+        assert_eq!(parse("001"), None);
+        assert_eq!(parse("0"), None);
+        assert_eq!(parse("13"), None);
+        assert_eq!(parse("March"), None);
+    }
+
+    #[test]
+    fn number_name_month_dirs() {
+        let parse = |name| MonthDirStyle::NumberName.parse_month(name);
+        assert_eq!(parse("03 - March"), Some(3));
+        assert_eq!(parse("3-Mar"), Some(3));
+        assert_eq!(parse("1 - January"), Some(1));
+        assert_eq!(parse("10 - October"), Some(10));
+        // the number and the name must agree
+        assert_eq!(parse("1 - October"), None);
+        assert_eq!(parse("03"), None);
+        assert_eq!(parse("March"), None);
+    }
+
+    #[test]
+    fn name_month_dirs() {
+        let parse = |name| MonthDirStyle::Name.parse_month(name);
+        assert_eq!(parse("March"), Some(3));
+        assert_eq!(parse("mar"), Some(3));
+        assert_eq!(parse("Ma"), None);
+        assert_eq!(parse("Marc"), None);
+        assert_eq!(parse("3"), None);
+        assert_eq!(parse("03 - March"), None);
+    }
+
+    #[test]
+    fn any_month_dirs() {
+        let parse = |name| MonthDirStyle::Any.parse_month(name);
+        for name in ["3", "03", "03 - March", "March", "Mar"].iter() {
+            assert_eq!(parse(name), Some(3), "{}", name);
+        }
+        assert_eq!(parse("1"), Some(1));
+        assert_eq!(parse("10"), Some(10));
+        assert_eq!(parse("1 - October"), None);
+        assert_eq!(parse("Archive"), None);
+    }
+
+    #[test]
+    fn listed_files_take_batch_and_run_date_from_their_directories() {
+        let regex = get_regex("agricor_potency").unwrap().unwrap();
+        let listed = |path: &str| CgFile::from_listed_path(PathBuf::from(path), &regex);
+
+        let file = listed("/data/2020/5/01-May-2020/agricor potency X/cert generator a.xlsm");
+        assert_eq!(file.batch.as_deref(), Some("agricor potency X"));
+        assert_eq!(file.run_date, Date::new(2020, 5, 1));
+
+        // an ISO dated directory, further up than the batch folder
+        let file = listed("/data/2020-05-02/agricor potency Y/old/cert generator a.xlsm");
+        assert_eq!(file.batch, None);
+        assert_eq!(file.run_date, Date::new(2020, 5, 2));
+
+        let file = listed("cert generator agricor potency X.xlsm");
+        assert_eq!((file.batch, file.run_date), (None, None));
+    }
+}
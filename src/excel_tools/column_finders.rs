@@ -1,51 +1,211 @@
+use std::fmt;
+
 use calamine::{DataType, Range};
+use regex::Regex;
+
+/// Rows at the top of a sheet searched for its header row.
+pub const HEADER_SEARCH_ROWS: u32 = 20;
+
+/// A regular expression matched against headers, compiled once when its extractor is built.
+#[derive(Clone)]
+pub struct HeaderRegex {
+    pattern: &'static str,
+    regex: Result<Regex, regex::Error>,
+}
+
+impl HeaderRegex {
+    pub fn new(pattern: &'static str) -> Self {
+        HeaderRegex {
+            pattern,
+            regex: Regex::new(pattern),
+        }
+    }
+}
+
+impl fmt::Debug for HeaderRegex {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self.pattern)
+    }
+}
 
+#[derive(Clone, Debug)]
 pub enum MatchMethod {
     StartsWith(&'static str),
+    /// Starts with the text followed by the end of the header or by a character other than a
+    /// letter or digit, so "MGK 264 1" does not match "MGK 264 10".
+    StartsWithWord(&'static str),
     Exact(&'static str),
+    /// Equal ignoring case.
+    CaseInsensitive(&'static str),
+    /// Equal once leading and trailing whitespace is trimmed and every run of whitespace,
+    /// including line breaks, is read as one space.
+    NormalizedWhitespace(&'static str),
+    Contains(&'static str),
+    /// Matched anywhere in the header by a regular expression.
+    Regex(HeaderRegex),
+    /// Any of several methods, tried in order.
+    Aliases(Vec<MatchMethod>),
+}
+
+impl MatchMethod {
+    /// Match headers by the regular expression `pattern`.
+    pub fn regex(pattern: &'static str) -> Self {
+        MatchMethod::Regex(HeaderRegex::new(pattern))
+    }
+
+    pub fn is_match(&self, header: &str) -> bool {
+        self.matching(header).is_some()
+    }
+
+    /// The method that matches `header`: the first alias to match it, for `Aliases`.
+    pub fn matching(&self, header: &str) -> Option<&MatchMethod> {
+        let is_match = match self {
+            MatchMethod::StartsWith(match_str) => header.starts_with(match_str),
+            MatchMethod::StartsWithWord(match_str) => header
+                .strip_prefix(match_str)
+                .is_some_and(|rest| rest.chars().next().is_none_or(|c| !c.is_alphanumeric())),
+            MatchMethod::Exact(match_str) => header == *match_str,
+            MatchMethod::CaseInsensitive(match_str) => {
+                header.to_lowercase() == match_str.to_lowercase()
+            }
+            MatchMethod::NormalizedWhitespace(match_str) => {
+                normalize_whitespace(header) == normalize_whitespace(match_str)
+            }
+            MatchMethod::Contains(match_str) => header.contains(match_str),
+            MatchMethod::Regex(header_regex) => match &header_regex.regex {
+                Ok(regex) => regex.is_match(header),
+                // check_structure rejects these before any workbook is read
+                Err(e) => panic!("Invalid header pattern '{}': {}", header_regex.pattern, e),
+            },
+            MatchMethod::Aliases(aliases) => return aliases.iter().find_map(|a| a.matching(header)),
+        };
+        if is_match {
+            Some(self)
+        } else {
+            None
+        }
+    }
+
+    /// Every regular expression used by the method that does not compile.
+    pub fn invalid_patterns(&self) -> Vec<String> {
+        match self {
+            MatchMethod::Regex(header_regex) => match &header_regex.regex {
+                Ok(_) => vec![],
+                Err(e) => vec![format!("Invalid header pattern '{}': {}", header_regex.pattern, e)],
+            },
+            MatchMethod::Aliases(aliases) => {
+                aliases.iter().flat_map(|a| a.invalid_patterns()).collect()
+            }
+            _ => vec![],
+        }
+    }
+}
+
+fn normalize_whitespace(s: &str) -> String {
+    s.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+/// The header text of column `col`, read from the `header_height` rows starting at
+/// `header_row` and joined with spaces.
+///
+/// In a header spanning several rows, an empty cell above the last header row takes the text to
+/// its left, as a cell merged across several columns leaves its text in the first of them.
+pub fn header_text(
+    ws: &Range<DataType>,
+    col: u32,
+    header_row: u32,
+    header_height: u32,
+) -> Option<String> {
+    let last_row = header_row + header_height.max(1) - 1;
+    let parts: Vec<&str> = (header_row..=last_row)
+        .filter_map(|row| {
+            let cell_text = |c: u32| match ws.get_value((row, c)) {
+                Some(DataType::String(s)) if !s.is_empty() => Some(s.as_str()),
+                _ => None,
+            };
+            if row == last_row {
+                cell_text(col)
+            } else {
+                (0..=col).rev().find_map(cell_text)
+            }
+        })
+        .collect();
+    if parts.is_empty() {
+        None
+    } else {
+        Some(parts.join(" "))
+    }
+}
+
+/// Find every column whose header, in the `header_height` rows starting at `header_row`,
+/// satisfies `match_method`.
+///
+/// Aliases are tried in order, so only the columns matching the first alias that matches any
+/// are returned.
+pub fn find_header_cols(
+    ws: &Range<DataType>,
+    match_method: &MatchMethod,
+    header_row: u32,
+    header_height: u32,
+) -> Vec<u32> {
+    match match_method {
+        MatchMethod::Aliases(aliases) => aliases
+            .iter()
+            .map(|a| find_header_cols(ws, a, header_row, header_height))
+            .find(|cols| !cols.is_empty())
+            .unwrap_or_default(),
+        _ => match ws.end() {
+            Some((_, max_col)) => (0..=max_col)
+                .filter(|col| {
+                    header_text(ws, *col, header_row, header_height)
+                        .is_some_and(|text| match_method.is_match(&text))
+                })
+                .collect(),
+            None => vec![],
+        },
+    }
+}
+
+/// Find the first column whose header, in the `header_height` rows starting at `header_row`,
+/// satisfies `match_method`.
+pub fn find_header_col(
+    ws: &Range<DataType>,
+    match_method: &MatchMethod,
+    header_row: u32,
+    header_height: u32,
+) -> Option<u32> {
+    find_header_cols(ws, match_method, header_row, header_height)
+        .first()
+        .copied()
+}
+
+/// Find the first of the top `HEADER_SEARCH_ROWS` rows starting a header, `header_height` rows
+/// tall, with a column matching each of `expected`.
+pub fn find_header_row(
+    ws: &Range<DataType>,
+    expected: &[MatchMethod],
+    header_height: u32,
+) -> Option<u32> {
+    (0..HEADER_SEARCH_ROWS).find(|row| {
+        expected
+            .iter()
+            .all(|m| find_header_col(ws, m, *row, header_height).is_some())
+    })
 }
 
 pub fn header_match(
     ws: &Range<DataType>,
-    match_method: MatchMethod,
+    match_method: &MatchMethod,
     header_row: u32,
+    header_height: u32,
     row_count: u32,
     start_offset: Option<u32>,
 ) -> Range<DataType> {
     // set row offset for the returned range
-    let range_offset: u32;
-    match start_offset {
-        Some(offset) => range_offset = offset,
-        None => range_offset = 1,
-    };
-
-    let header_max_col = (ws.width() - 1) as u32;
-    let header = ws.range((header_row, 0), (header_row, header_max_col));
-    for cell in header.cells() {
-        let (_row, col, val) = cell;
-        match val {
-            DataType::String(s) => {
-                match match_method {
-                    MatchMethod::StartsWith(match_str) => {
-                        if s.starts_with(match_str) {
-                            return ws.range(
-                                (range_offset, col as u32),
-                                (range_offset + row_count, col as u32),
-                            );
-                        }
-                    }
-                    MatchMethod::Exact(match_str) => {
-                        if s == match_str {
-                            return ws.range(
-                                (range_offset, col as u32),
-                                (range_offset + row_count, col as u32),
-                            )
-                        }
-                    }
-                }
-            }
-            _ => continue,
-        }
+    let range_offset = start_offset.unwrap_or(header_row + header_height);
+
+    if let Some(col) = find_header_col(ws, match_method, header_row, header_height) {
+        return ws.range((range_offset, col), (range_offset + row_count, col));
     }
     // return range filled with a default string
     let mut default = Range::new((0, 0), (row_count, 0));
@@ -53,3 +213,93 @@ pub fn header_match(
         .for_each(|i| default.set_value((i as u32, 0), DataType::String("NA".to_string())));
     default
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A sheet whose rows are given as strings, an empty string leaving its cell empty.
+    fn sheet(rows: &[&[&str]]) -> Range<DataType> {
+        let width = rows.iter().map(|r| r.len()).max().unwrap_or(1) as u32;
+        let mut ws = Range::new((0, 0), (rows.len() as u32 - 1, width - 1));
+        for (row, cells) in rows.iter().enumerate() {
+            for (col, cell) in cells.iter().enumerate() {
+                if !cell.is_empty() {
+                    ws.set_value((row as u32, col as u32), DataType::String(cell.to_string()));
+                }
+            }
+        }
+        ws
+    }
+
+    #[test]
+    fn match_methods() {
+        assert!(MatchMethod::StartsWith("Test ID").is_match("Test ID (Metrc)"));
+        assert!(!MatchMethod::Exact("CBD").is_match("CBDa"));
+        assert!(MatchMethod::CaseInsensitive("sample name").is_match("SAMPLE NAME"));
+        assert!(MatchMethod::NormalizedWhitespace("Sample  Name").is_match(" Sample\nName "));
+        assert!(MatchMethod::Contains("Manifest").is_match("Metrc Manifest #"));
+        assert!(MatchMethod::regex(r"(?i)^test\s*id").is_match("TestID"));
+        assert!(!MatchMethod::regex(r"(?i)^test\s*id").is_match("Latest ID"));
+    }
+
+    #[test]
+    fn starts_with_word_ends_at_a_word_boundary() {
+        let mgk = MatchMethod::StartsWithWord("MGK 264 1");
+        assert!(mgk.is_match("MGK 264 1"));
+        assert!(mgk.is_match("MGK 264 1 (ppm)"));
+        assert!(mgk.is_match("MGK 264 1-ppm"));
+        assert!(!mgk.is_match("MGK 264 10"));
+        assert!(!MatchMethod::StartsWithWord("Spinosad A").is_match("Spinosad AB"));
+
+        let ws = sheet(&[&["MGK 264 10", "MGK 264 1", "MGK 264 2"]]);
+        assert_eq!(find_header_cols(&ws, &mgk, 0, 1), [1]);
+    }
+
+    #[test]
+    fn aliases_report_the_alias_that_matched() {
+        let aliases = MatchMethod::Aliases(vec![
+            MatchMethod::Exact("Sample Name"),
+            MatchMethod::StartsWith("SAMPLE NAME"),
+        ]);
+        let alias = aliases.matching("SAMPLE NAME (client)");
+        assert!(matches!(alias, Some(MatchMethod::StartsWith("SAMPLE NAME"))));
+        assert!(aliases.matching("Name").is_none());
+    }
+
+    #[test]
+    fn invalid_patterns_are_reported() {
+        assert!(MatchMethod::regex("^ok$").invalid_patterns().is_empty());
+        let aliases = MatchMethod::Aliases(vec![MatchMethod::regex("(unclosed")]);
+        assert_eq!(aliases.invalid_patterns().len(), 1);
+    }
+
+    #[test]
+    fn find_cols_in_header_row() {
+        let ws = sheet(&[&["Title"], &["Test ID", "MGK 264 10", "MGK 264 1", "MGK 264 1"]]);
+        let exact = MatchMethod::Exact("MGK 264 1");
+        assert_eq!(find_header_cols(&ws, &exact, 1, 1), [2, 3]);
+        let starts_with = MatchMethod::StartsWith("MGK 264 1");
+        assert_eq!(find_header_col(&ws, &starts_with, 1, 1), Some(1));
+        assert_eq!(find_header_row(&ws, &[MatchMethod::Exact("Test ID")], 1), Some(1));
+        assert_eq!(find_header_row(&ws, &[MatchMethod::Exact("Sample")], 1), None);
+    }
+
+    #[test]
+    fn multi_row_headers_take_merged_text() {
+        let ws = sheet(&[&["TYM", "", "TA"], &["Count", "Plate", "Count"]]);
+        assert_eq!(header_text(&ws, 1, 0, 2), Some("TYM Plate".to_string()));
+        assert_eq!(header_text(&ws, 2, 0, 2), Some("TA Count".to_string()));
+        let ta_count = MatchMethod::Exact("TA Count");
+        assert_eq!(find_header_col(&ws, &ta_count, 0, 2), Some(2));
+    }
+
+    #[test]
+    fn missing_header_column_is_filled_with_na() {
+        let ws = sheet(&[&["Test ID"], &["T-1"], &["T-2"]]);
+        let col = header_match(&ws, &MatchMethod::Exact("Test ID"), 0, 1, 1, None);
+        assert_eq!(col.get((0, 0)), Some(&DataType::String("T-1".to_string())));
+        let missing = header_match(&ws, &MatchMethod::Exact("Batch"), 0, 1, 1, None);
+        assert_eq!(missing.get((1, 0)), Some(&DataType::String("NA".to_string())));
+    }
+}
@@ -1,43 +1,147 @@
-use std::rc::Rc;
-
-use calamine::{DataType, Range, Reader, Xlsx};
+use calamine::{DataType, Range, Reader};
+use std::collections::{HashMap, VecDeque};
 use std::io::{Read, Seek};
+use std::str::FromStr;
+
+use super::column_finders::{find_header_col, find_header_row, header_match, MatchMethod};
+use crate::dates::{format_time, Date, DateSystem};
+
+/// Rows of data read from each sheet.
+pub const MAX_ROWS: u32 = 200;
 
-pub fn find_active_rows(ws: &Range<DataType>, search_col: u32, last_row: Option<u32>) -> Vec<u32> {
-    let bottom_row = match last_row {
-        Some(num) => num,
-        None => 200,
+/// The `row_count` rows from `first_row` with a value in any of `key_cols`, or in all of them
+/// when `require_all` is set.
+pub fn find_active_rows(
+    ws: &Range<DataType>,
+    key_cols: &[u32],
+    require_all: bool,
+    first_row: u32,
+    row_count: u32,
+) -> Vec<u32> {
+    let has_value = |row_num: u32, col: &u32| match ws.get_value((row_num, *col)) {
+        Some(DataType::Error(_e)) => false,
+        Some(DataType::Empty) => false,
+        Some(DataType::String(s)) => !s.is_empty(),
+        Some(_) => true,
+        None => false,
     };
-    let mut active_rows: Vec<u32> = vec![];
-    for row_num in 1..=bottom_row {
-        let val = ws.get_value((row_num, search_col));
-        match val {
-            Some(dt) => match dt {
-                DataType::Error(_e) => continue,
-                DataType::Empty => continue,
-                DataType::String(s) => {
-                    if s == "" {
-                        continue;
-                    } else {
-                        active_rows.push(row_num)
-                    }
-                }
-                _ => active_rows.push(row_num),
-            },
-            None => continue,
+    (first_row..first_row + row_count)
+        .filter(|row_num| {
+            if require_all {
+                key_cols.iter().all(|col| has_value(*row_num, col))
+            } else {
+                key_cols.iter().any(|col| has_value(*row_num, col))
+            }
+        })
+        .collect()
+}
+
+/// The rows of a workbook holding samples.
+pub struct SourceRows {
+    /// Position of each sample's row among the data rows of the row source sheet.
+    pub rows: Vec<u32>,
+}
+
+/// Find the rows holding samples using the first row source whose sheet and key columns are
+/// all present in the workbook.
+pub fn find_source_rows<R: Reader>(
+    wb: &mut R,
+    sources: &[RowSource],
+    row_count: u32,
+) -> Option<SourceRows> {
+    sources.iter().find_map(|source| {
+        let ws = wb.worksheet_range(source.sheet_name)?.ok()?;
+        let layout = source.layout.locate(&ws);
+        let key_cols = source
+            .key_cols
+            .iter()
+            .map(|indexer| key_col(&ws, indexer, &layout))
+            .collect::<Option<Vec<u32>>>()?;
+        let data_start = layout.data_start();
+        let active_rows =
+            find_active_rows(&ws, &key_cols, source.require_all, data_start, row_count);
+        let rows = active_rows.iter().map(|row| row - data_start).collect();
+        Some(SourceRows { rows })
+    })
+}
+
+/// Rows of a sheet matched to samples by the value in its join key column.
+pub struct JoinedRows {
+    /// For each sample, the position of its row among the sheet's data rows, if it has one.
+    pub rows: Vec<Option<u32>>,
+    /// Keys of rows in the sheet that match no sample.
+    pub unmatched: Vec<String>,
+}
+
+/// Match the first `row_count` data rows of a sheet to the samples with `keys`, by the value in
+/// the sheet's `sheet_col` column. Returns `None` if the key column cannot be found.
+///
+/// Samples sharing a key are matched to the rows with that key in order, so the second sample
+/// named "Blue Dream" gets the second "Blue Dream" row.
+pub fn join_rows(
+    ws: &Range<DataType>,
+    layout: &SheetLayout,
+    sheet_col: &ColIndexer,
+    keys: &[String],
+    row_count: u32,
+) -> Option<JoinedRows> {
+    let col = key_col(ws, sheet_col, layout)?;
+    // data row positions of each key, in order
+    let mut sheet_rows: HashMap<String, VecDeque<u32>> = HashMap::new();
+    let mut key_order = vec![];
+    for row_idx in 0..row_count {
+        let key = key_value(ws.get_value((layout.data_start() + row_idx, col)));
+        if key.is_empty() {
+            continue;
+        }
+        if !sheet_rows.contains_key(&key) {
+            key_order.push(key.clone());
         }
+        sheet_rows.entry(key).or_default().push_back(row_idx);
     }
+    let rows = keys
+        .iter()
+        .map(|k| sheet_rows.get_mut(k).and_then(|rows| rows.pop_front()))
+        .collect();
+    let unmatched = key_order
+        .into_iter()
+        .flat_map(|key| {
+            let left = sheet_rows[&key].len();
+            std::iter::repeat_n(key, left)
+        })
+        .collect();
+    Some(JoinedRows { rows, unmatched })
+}
 
-    active_rows
+/// The text a cell is matched on when joining sheets by key.
+pub fn key_value(cell: Option<&DataType>) -> String {
+    match cell {
+        Some(DataType::String(s)) => s.trim().to_string(),
+        Some(DataType::Empty) | Some(DataType::Error(_)) | None => String::new(),
+        Some(dt) => dt.to_string(),
+    }
+}
+
+/// The column a row source or join key refers to, if it can be found in `ws`.
+pub fn key_col(ws: &Range<DataType>, indexer: &ColIndexer, layout: &SheetLayout) -> Option<u32> {
+    match indexer {
+        ColIndexer::Index(i) => Some(*i),
+        ColIndexer::Header(match_method) => {
+            find_header_col(ws, match_method, layout.header_row, layout.header_height)
+        }
+        ColIndexer::CellValue(..) | ColIndexer::DefaultValue(_) => None,
+    }
 }
 
 pub fn extract_column(
     range: &Range<DataType>,
     col_indexer: &ColIndexer,
+    layout: &SheetLayout,
     max_row: u32,
 ) -> Range<DataType> {
+    let data_start = layout.data_start();
     match col_indexer {
-        ColIndexer::Index(i) => range.range((1, *i), (max_row, *i)),
+        ColIndexer::Index(i) => range.range((data_start, *i), (data_start + max_row - 1, *i)),
         ColIndexer::CellValue(row, col) => {
             // create a new range and fill with a single value from a specific cell
             let mut new_range = Range::new((0, 0), (max_row, 0));
@@ -54,37 +158,158 @@ pub fn extract_column(
             (0..new_range.height()).for_each(|i| new_range.set_value((i as u32, 0), dt.clone()));
             new_range
         },
-        ColIndexer::ColFindFunc(func) => func(range, max_row)
+        ColIndexer::Header(match_method) => header_match(
+            range,
+            match_method,
+            layout.header_row,
+            layout.header_height,
+            max_row,
+            Some(data_start),
+        ),
     }
 }
 
 pub fn extract_sheet_columns(
     range: &Range<DataType>,
     sheet: &Sheet,
+    layout: &SheetLayout,
     max_row: u32,
 ) -> Vec<Range<DataType>> {
     let mut cols = vec![];
     for indexer in sheet.col_indexers.iter() {
-        cols.push(extract_column(range, indexer, max_row))
+        cols.push(extract_column(range, indexer, layout, max_row))
     }
     cols
 }
 
-pub fn rows_from_cols(cols: Vec<Range<DataType>>, active_rows: Vec<u32>) -> Vec<Vec<DataType>> {
-    let mut rows_data = vec![];
-    for row in active_rows.iter() {
-        let mut cur_row = vec![];
-        for col in cols.iter() {
-            cur_row.push(
-                // Get range values by relative position in column.
-                // Subtract 1 from the row since the `active_rows` should always
-                // be offset by 1.
-                col.get(((*row - 1) as usize, 0 as usize)).unwrap().clone(),
-            );
+/// Set the type hint of the column named `col_name`, replacing any hint it already has.
+///
+/// Does nothing if the sheet has no such column.
+pub fn set_col_type(sheet: &mut Sheet, col_name: &str, col_type: ColType) {
+    if let Some(name) = sheet.col_names.iter().find(|n| **n == col_name) {
+        sheet.col_types.retain(|(n, _)| n != name);
+        sheet.col_types.push((name, col_type));
+    }
+}
+
+/// Convert the values of every typed column of `sheet` into the representation of its type.
+pub fn apply_col_types(cols: &mut [Range<DataType>], sheet: &Sheet, date_system: DateSystem) {
+    for (col_name, col_type) in sheet.col_types.iter() {
+        if let Some(i) = sheet.col_names.iter().position(|n| n == col_name) {
+            if let Some(col) = cols.get_mut(i) {
+                convert_column(col, col_type, date_system);
+            }
+        }
+    }
+}
+
+fn convert_column(col: &mut Range<DataType>, col_type: &ColType, date_system: DateSystem) {
+    let start = match col.start() {
+        Some(start) => start,
+        None => return,
+    };
+    for i in 0..col.height() as u32 {
+        let pos = (start.0 + i, start.1);
+        if let Some(val) = col.get_value(pos) {
+            let converted = convert_value(val, col_type, date_system);
+            col.set_value(pos, converted);
+        }
+    }
+}
+
+fn convert_value(val: &DataType, col_type: &ColType, date_system: DateSystem) -> DataType {
+    let serial = match val {
+        DataType::Float(f) => *f,
+        DataType::Int(i) => *i as f64,
+        _ => return val.clone(),
+    };
+    match col_type {
+        ColType::Date => match Date::from_excel_serial(serial, date_system) {
+            Some((date, _)) => DataType::String(date.to_string()),
+            None => val.clone(),
+        },
+        ColType::DateTime => match Date::from_excel_serial(serial, date_system) {
+            Some((date, seconds)) => {
+                DataType::String(format!("{}T{}", date, format_time(seconds)))
+            }
+            None => val.clone(),
+        },
+        ColType::Integer if serial.fract() == 0.0 => DataType::Int(serial as i64),
+        ColType::Integer => val.clone(),
+        ColType::Text => DataType::String(serial.to_string()),
+    }
+}
+
+/// Read which date system a workbook uses, from the `workbookPr` element of an xlsx/xlsm
+/// workbook or the `BrtWbProp` record of an xlsb one.
+///
+/// Returns `None` for formats the flag cannot be read from, i.e. xls workbooks, whose
+/// `DATEMODE` record calamine does not expose.
+pub fn read_date_system<RS: Read + Seek>(reader: RS) -> Option<DateSystem> {
+    let mut archive = zip::ZipArchive::new(reader).ok()?;
+    let is_1904 = if archive.file_names().any(|name| name == "xl/workbook.xml") {
+        let mut workbook_xml = String::new();
+        archive.by_name("xl/workbook.xml").ok()?.read_to_string(&mut workbook_xml).ok()?;
+        workbook_xml.contains(r#"date1904="1""#) || workbook_xml.contains(r#"date1904="true""#)
+    } else {
+        let mut workbook_bin = vec![];
+        archive.by_name("xl/workbook.bin").ok()?.read_to_end(&mut workbook_bin).ok()?;
+        xlsb_is_1904(&workbook_bin)?
+    };
+    Some(if is_1904 { DateSystem::Excel1904 } else { DateSystem::Excel1900 })
+}
+
+/// Find the `f1904` flag, the lowest bit of the `BrtWbProp` record, in an xlsb workbook part.
+fn xlsb_is_1904(mut records: &[u8]) -> Option<bool> {
+    const BRT_WB_PROP: u32 = 0x99;
+    // record types take up to 2 bytes and sizes up to 4, 7 bits a byte, low bits first
+    fn var_int(bytes: &mut &[u8], max_len: usize) -> Option<u32> {
+        let mut value = 0;
+        for i in 0..max_len {
+            let (byte, rest) = bytes.split_first()?;
+            *bytes = rest;
+            value |= u32::from(byte & 0x7f) << (7 * i);
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+        Some(value)
+    }
+    while !records.is_empty() {
+        let record_type = var_int(&mut records, 2)?;
+        let size = var_int(&mut records, 4)? as usize;
+        let data = records.get(..size)?;
+        if record_type == BRT_WB_PROP {
+            return data.first().map(|flags| flags & 1 == 1);
         }
-        rows_data.push(cur_row);
+        records = &records[size..];
     }
-    rows_data
+    None
+}
+
+/// Turn a sheet's extracted columns into one row per sample, where `rows` holds the position
+/// of each sample's row among the sheet's data rows. A sample without a row gets `width`
+/// empty cells.
+pub fn rows_from_cols(
+    cols: &[Range<DataType>],
+    rows: &[Option<u32>],
+    width: usize,
+) -> Vec<Vec<DataType>> {
+    rows.iter()
+        .map(|row| match row {
+            // Get range values by relative position in column,
+            // since the extracted columns start at the first data row.
+            Some(row) if !cols.is_empty() => cols
+                .iter()
+                .map(|col| {
+                    col.get((*row as usize, 0))
+                        .cloned()
+                        .unwrap_or(DataType::Empty)
+                })
+                .collect(),
+            _ => vec![DataType::Empty; width],
+        })
+        .collect()
 }
 
 pub fn make_header(sheets: &[SheetExtractor]) -> Vec<&'static str> {
@@ -97,19 +322,144 @@ pub fn make_header(sheets: &[SheetExtractor]) -> Vec<&'static str> {
         .collect()
 }
 
-#[derive(Clone)]
+/// Whether any column of `sheets` is converted to a date once `overrides` have been applied
+/// with `set_col_type`, in which case the workbook's date system matters.
+pub fn has_date_columns(sheets: &[SheetExtractor], overrides: &[(String, ColType)]) -> bool {
+    let is_date = |t: &ColType| matches!(t, ColType::Date | ColType::DateTime);
+    sheets.iter().any(|s| {
+        let (col_names, col_types) = match s {
+            SheetExtractor::Multi(s) => (&s.col_names, &s.col_types),
+            SheetExtractor::Single(s) => (&s.col_names, &s.col_types),
+        };
+        col_names.iter().any(|name| {
+            let declared = col_types.iter().find(|(n, _)| n == name);
+            // the last override of a column is the one left in place
+            let overridden = overrides.iter().rev().find(|(n, _)| n == name);
+            overridden
+                .map(|(_, t)| t)
+                .or(declared.map(|(_, t)| t))
+                .is_some_and(is_date)
+        })
+    })
+}
+
+#[derive(Clone, Debug)]
 pub enum ColIndexer {
     Index(u32),
     CellValue(u32, u32),
     DefaultValue(DataType),
-    ColFindFunc(Rc<dyn Fn(&Range<DataType>, u32) -> Range<DataType>>)
+    Header(MatchMethod),
 }
 
-#[derive(Clone)]
+/// How the values of a column should be rendered in the output.
+#[derive(Clone, Debug)]
+pub enum ColType {
+    /// An Excel date serial, written as an ISO-8601 date.
+    Date,
+    /// An Excel date serial with a time of day, written as an ISO-8601 date and time.
+    DateTime,
+    Integer,
+    Text,
+}
+
+impl FromStr for ColType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "date" => Ok(ColType::Date),
+            "datetime" => Ok(ColType::DateTime),
+            "integer" | "int" => Ok(ColType::Integer),
+            "text" => Ok(ColType::Text),
+            _ => Err(format!("Unknown column type '{}'", s)),
+        }
+    }
+}
+
+#[derive(Clone, Default)]
 pub struct Sheet {
     pub sheet_name: &'static str,
     pub col_names: Vec<&'static str>,
     pub col_indexers: Vec<ColIndexer>,
+    /// Type hints for columns, keyed by column name. Untyped columns are written as read.
+    pub col_types: Vec<(&'static str, ColType)>,
+    /// Column matching the sheet's rows to samples by value instead of by position.
+    pub join_key: Option<JoinKey>,
+    pub layout: SheetLayout,
+}
+
+/// How a sheet's rows are matched to samples by value, for sheets that may be sorted
+/// differently from the sheet samples are found in.
+#[derive(Clone, Debug)]
+pub struct JoinKey {
+    /// Column of the sheet holding each row's key. Only `Index` and `Header` indexers identify
+    /// a column.
+    pub sheet_col: ColIndexer,
+    /// Output column, of a sheet extracted before this one, holding each sample's key.
+    pub sample_col: &'static str,
+}
+
+/// Where a sheet's column headers and data are.
+#[derive(Clone, Debug)]
+pub struct SheetLayout {
+    pub header_row: u32,
+    /// Rows the header spans, which are matched against as one header joined with spaces.
+    pub header_height: u32,
+    /// First row of data, if not the row after the header.
+    pub data_start: Option<u32>,
+    /// Headers that identify the header row, which is then found by searching the top of the
+    /// sheet for them instead of taken from `header_row`.
+    pub expected_headers: Vec<MatchMethod>,
+}
+
+impl SheetLayout {
+    pub fn data_start(&self) -> u32 {
+        self.data_start.unwrap_or(self.header_row + self.header_height)
+    }
+
+    /// The layout of `ws`, with the header row moved to the row holding the expected headers.
+    ///
+    /// A declared `data_start` keeps its distance from the header row. The layout is returned
+    /// unchanged when no row holds the expected headers.
+    pub fn locate(&self, ws: &Range<DataType>) -> SheetLayout {
+        if self.expected_headers.is_empty() {
+            return self.clone();
+        }
+        match find_header_row(ws, &self.expected_headers, self.header_height) {
+            Some(header_row) => SheetLayout {
+                header_row,
+                data_start: self
+                    .data_start
+                    .map(|row| (row + header_row).saturating_sub(self.header_row)),
+                ..self.clone()
+            },
+            None => self.clone(),
+        }
+    }
+}
+
+impl Default for SheetLayout {
+    /// A one row header on the first row, with data from the second row.
+    fn default() -> Self {
+        SheetLayout {
+            header_row: 0,
+            header_height: 1,
+            data_start: None,
+            expected_headers: vec![],
+        }
+    }
+}
+
+/// A sheet whose key columns decide which rows of a workbook hold samples: a row is extracted
+/// when its key columns have values.
+#[derive(Clone, Default)]
+pub struct RowSource {
+    pub sheet_name: &'static str,
+    /// Only `Index` and `Header` indexers identify a column to look for values in.
+    pub key_cols: Vec<ColIndexer>,
+    /// Require a value in every key column rather than in any of them.
+    pub require_all: bool,
+    pub layout: SheetLayout,
 }
 
 pub enum SheetExtractor {
@@ -120,17 +470,21 @@ pub enum SheetExtractor {
 pub struct PotentialSheet {
     pub sheet_name: &'static str,
     pub col_indexers: Vec<ColIndexer>,
+    pub layout: SheetLayout,
     pub sheet_for_val: &'static str,
     pub validator: fn(ws: &Range<DataType>) -> bool,
 }
 
+#[derive(Default)]
 pub struct SheetSelector {
     pub col_names: Vec<&'static str>,
     pub potential_sheets: Vec<PotentialSheet>,
+    pub col_types: Vec<(&'static str, ColType)>,
+    pub join_key: Option<JoinKey>,
 }
 
-fn sheet_from_selector<RS: Read + Seek>(
-    wb: &mut Xlsx<RS>,
+pub fn sheet_from_selector<R: Reader>(
+    wb: &mut R,
     selector: &SheetSelector,
 ) -> Option<Sheet> {
     for p_sheet in selector.potential_sheets.iter() {
@@ -140,6 +494,9 @@ fn sheet_from_selector<RS: Read + Seek>(
                     sheet_name: p_sheet.sheet_name,
                     col_names: selector.col_names.clone(),
                     col_indexers: p_sheet.col_indexers.clone(),
+                    col_types: selector.col_types.clone(),
+                    join_key: selector.join_key.clone(),
+                    layout: p_sheet.layout.clone(),
                 });
             }
         } else {
@@ -149,8 +506,8 @@ fn sheet_from_selector<RS: Read + Seek>(
     None
 }
 
-pub fn validate_extractors<RS: Read + Seek>(
-    wb: &mut Xlsx<RS>,
+pub fn validate_extractors<R: Reader>(
+    wb: &mut R,
     extractors: &[SheetExtractor],
 ) -> Option<Vec<Sheet>> {
     let mut sheets = vec![];
@@ -166,3 +523,224 @@ pub fn validate_extractors<RS: Read + Seek>(
     }
     Some(sheets)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A one column sheet with a "Sample" header above `keys`, an empty key leaving its cell
+    /// empty.
+    fn key_sheet(keys: &[&str]) -> Range<DataType> {
+        let mut ws = Range::new((0, 0), (keys.len() as u32, 0));
+        ws.set_value((0, 0), DataType::String("Sample".to_string()));
+        for (i, key) in keys.iter().enumerate() {
+            if !key.is_empty() {
+                ws.set_value((i as u32 + 1, 0), DataType::String(key.to_string()));
+            }
+        }
+        ws
+    }
+
+    fn join(sheet_keys: &[&str], sample_keys: &[&str]) -> Option<JoinedRows> {
+        let keys: Vec<String> = sample_keys.iter().map(|k| k.to_string()).collect();
+        let sample_col = ColIndexer::Header(MatchMethod::Exact("Sample"));
+        join_rows(&key_sheet(sheet_keys), &SheetLayout::default(), &sample_col, &keys, 10)
+    }
+
+    #[test]
+    fn rows_are_matched_by_key_not_position() {
+        let joined = join(&["Sweet", "", "Sour "], &["Sour", "Sweet", "Sage"]).unwrap();
+        assert_eq!(joined.rows, [Some(2), Some(0), None]);
+        assert!(joined.unmatched.is_empty());
+    }
+
+    #[test]
+    fn repeated_keys_are_matched_in_order() {
+        let joined = join(&["Sour", "Sweet", "Sour", "Sour"], &["Sour", "Sour"]).unwrap();
+        assert_eq!(joined.rows, [Some(0), Some(2)]);
+        assert_eq!(joined.unmatched, ["Sour", "Sweet"]);
+    }
+
+    #[test]
+    fn missing_key_column_cannot_be_joined() {
+        let keys = vec!["Sour".to_string()];
+        let missing = ColIndexer::Header(MatchMethod::Exact("Test ID"));
+        let ws = key_sheet(&["Sour"]);
+        assert!(join_rows(&ws, &SheetLayout::default(), &missing, &keys, 10).is_none());
+    }
+
+    #[test]
+    fn numeric_keys_match_their_text() {
+        assert_eq!(key_value(Some(&DataType::Float(12.0))), "12");
+        assert_eq!(key_value(Some(&DataType::String(" T-1 ".to_string()))), "T-1");
+        assert_eq!(key_value(Some(&DataType::Empty)), "");
+    }
+
+    #[test]
+    fn rows_from_cols_fills_missing_samples() {
+        let mut col = Range::new((1, 0), (2, 0));
+        col.set_value((1, 0), DataType::Int(1));
+        col.set_value((2, 0), DataType::Int(2));
+        let rows = rows_from_cols(&[col], &[Some(1), None, Some(0)], 1);
+        assert_eq!(rows, [[DataType::Int(2)], [DataType::Empty], [DataType::Int(1)]]);
+    }
+
+    #[test]
+    fn active_rows_need_any_or_all_keys() {
+        let mut ws = Range::new((0, 0), (3, 1));
+        ws.set_value((1, 0), DataType::String("T-1".to_string()));
+        ws.set_value((1, 1), DataType::String("Sour".to_string()));
+        ws.set_value((2, 1), DataType::String("Sweet".to_string()));
+        ws.set_value((3, 0), DataType::String(String::new()));
+        assert_eq!(find_active_rows(&ws, &[0, 1], false, 1, 3), [1, 2]);
+        assert_eq!(find_active_rows(&ws, &[0, 1], true, 1, 3), [1]);
+    }
+
+    /// An in-memory zip holding a single `name` part.
+    fn zip_with(name: &str, contents: &[u8]) -> std::io::Cursor<Vec<u8>> {
+        use std::io::Write;
+        let mut zip = zip::ZipWriter::new(std::io::Cursor::new(vec![]));
+        let options = zip::write::FileOptions::default()
+            .compression_method(zip::CompressionMethod::Stored);
+        zip.start_file(name, options).unwrap();
+        zip.write_all(contents).unwrap();
+        let mut cursor = zip.finish().unwrap();
+        cursor.set_position(0);
+        cursor
+    }
+
+    #[test]
+    fn date_system_of_xlsx() {
+        let read = |xml: &str| read_date_system(zip_with("xl/workbook.xml", xml.as_bytes()));
+        assert_eq!(read(r#"<workbookPr date1904="1"/>"#), Some(DateSystem::Excel1904));
+        assert_eq!(read(r#"<workbookPr date1904="true"/>"#), Some(DateSystem::Excel1904));
+        assert_eq!(read(r#"<workbookPr date1904="0"/>"#), Some(DateSystem::Excel1900));
+        assert_eq!(read("<workbookPr/>"), Some(DateSystem::Excel1900));
+    }
+
+    #[test]
+    fn date_system_of_xlsb() {
+        // BrtBeginBook, then BrtFileVersion with a 2 byte size, then BrtWbProp
+        let records = |wb_prop_flags: u8| {
+            let mut records = vec![0x83, 0x01, 0x00];
+            records.extend([0x80, 0x01, 0x82, 0x00]);
+            records.extend(std::iter::repeat_n(0, 2));
+            records.extend([0x99, 0x01, 0x04, wb_prop_flags, 0, 0, 0]);
+            records
+        };
+        assert_eq!(xlsb_is_1904(&records(0x01)), Some(true));
+        assert_eq!(xlsb_is_1904(&records(0x08)), Some(false));
+        assert_eq!(xlsb_is_1904(&records(0x01)[..9]), None);
+        assert_eq!(
+            read_date_system(zip_with("xl/workbook.bin", &records(0x09))),
+            Some(DateSystem::Excel1904)
+        );
+    }
+
+    #[test]
+    fn date_system_of_xls_is_unknown() {
+        // xls workbooks are compound files, not zips
+        let cfb = [0xd0, 0xcf, 0x11, 0xe0, 0xa1, 0xb1, 0x1a, 0xe1];
+        assert_eq!(read_date_system(std::io::Cursor::new(cfb)), None);
+    }
+
+    /// A column starting on the second row, holding `values`.
+    fn column(values: &[DataType]) -> Range<DataType> {
+        let mut col = Range::new((1, 0), (values.len() as u32, 0));
+        for (i, value) in values.iter().enumerate() {
+            col.set_value((i as u32 + 1, 0), value.clone());
+        }
+        col
+    }
+
+    fn text(s: &str) -> DataType {
+        DataType::String(s.to_string())
+    }
+
+    #[test]
+    fn values_are_converted_to_their_column_type() {
+        let convert = |val, col_type| convert_value(&val, &col_type, DateSystem::Excel1900);
+        assert_eq!(convert(DataType::Float(43831.0), ColType::Date), text("2020-01-01"));
+        assert_eq!(convert(DataType::Int(43831), ColType::Date), text("2020-01-01"));
+        assert_eq!(
+            convert(DataType::Float(43831.75), ColType::DateTime),
+            text("2020-01-01T18:00:00")
+        );
+        assert_eq!(convert(DataType::Float(12.0), ColType::Integer), DataType::Int(12));
+        assert_eq!(convert(DataType::Float(12.5), ColType::Integer), DataType::Float(12.5));
+        assert_eq!(convert(DataType::Float(12.5), ColType::Text), text("12.5"));
+        assert_eq!(convert(DataType::Int(7), ColType::Text), text("7"));
+    }
+
+    #[test]
+    fn non_numeric_cells_are_left_alone() {
+        for col_type in [ColType::Date, ColType::DateTime, ColType::Integer, ColType::Text] {
+            for val in [text("ND"), DataType::Empty, DataType::Bool(true)] {
+                assert_eq!(convert_value(&val, &col_type, DateSystem::Excel1900), val);
+            }
+        }
+        // before the first day of the date system
+        assert_eq!(
+            convert_value(&DataType::Float(-1.0), &ColType::Date, DateSystem::Excel1900),
+            DataType::Float(-1.0)
+        );
+    }
+
+    #[test]
+    fn column_types_are_applied_by_name() {
+        let mut sheet = Sheet {
+            col_names: vec!["Received", "Count", "Lot"],
+            col_types: vec![("Received", ColType::Text)],
+            ..Default::default()
+        };
+        // a later type replaces the earlier one, an unknown column is ignored
+        set_col_type(&mut sheet, "Received", ColType::Date);
+        set_col_type(&mut sheet, "Count", ColType::Integer);
+        set_col_type(&mut sheet, "Missing", ColType::Text);
+        assert_eq!(sheet.col_types.len(), 2);
+
+        let serial = DataType::Float(42369.0);
+        let mut cols = vec![
+            column(&[serial.clone(), text("pending")]),
+            column(&[DataType::Float(3.0), DataType::Empty]),
+            column(&[serial.clone(), DataType::Float(4.0)]),
+        ];
+        apply_col_types(&mut cols, &sheet, DateSystem::Excel1904);
+        assert_eq!(cols[0].get_value((1, 0)), Some(&text("2020-01-01")));
+        assert_eq!(cols[0].get_value((2, 0)), Some(&text("pending")));
+        assert_eq!(cols[1].get_value((1, 0)), Some(&DataType::Int(3)));
+        assert_eq!(cols[1].get_value((2, 0)), Some(&DataType::Empty));
+        assert_eq!(cols[2].get_value((1, 0)), Some(&serial));
+
+        // the same serial is four years earlier in the 1900 system
+        let mut cols = vec![column(&[serial])];
+        apply_col_types(&mut cols, &sheet, DateSystem::Excel1900);
+        assert_eq!(cols[0].get_value((1, 0)), Some(&text("2015-12-31")));
+    }
+
+    #[test]
+    fn date_columns_after_overrides() {
+        let sheets = [SheetExtractor::Single(Sheet {
+            col_names: vec!["Received", "Count"],
+            col_types: vec![("Received", ColType::DateTime)],
+            ..Default::default()
+        })];
+        let overrides = |types: &[(&str, ColType)]| -> Vec<(String, ColType)> {
+            types.iter().map(|(n, t)| (n.to_string(), t.clone())).collect()
+        };
+        assert!(has_date_columns(&sheets, &[]));
+        assert!(!has_date_columns(&sheets, &overrides(&[("Received", ColType::Text)])));
+        assert!(has_date_columns(
+            &sheets,
+            &overrides(&[("Received", ColType::Text), ("Received", ColType::Date)])
+        ));
+        assert!(!has_date_columns(&sheets[..0], &overrides(&[("Received", ColType::Date)])));
+
+        let selector = [SheetExtractor::Multi(SheetSelector {
+            col_names: vec!["Count"],
+            ..Default::default()
+        })];
+        assert!(!has_date_columns(&selector, &overrides(&[("Received", ColType::Date)])));
+        assert!(has_date_columns(&selector, &overrides(&[("Count", ColType::Date)])));
+    }
+}
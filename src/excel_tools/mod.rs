@@ -1,5 +1,8 @@
 mod helpers;
+mod validation;
+mod workbook;
 pub mod column_finders;
 
 pub use helpers::*;
-
+pub use validation::*;
+pub use workbook::*;
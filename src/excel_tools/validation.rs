@@ -0,0 +1,587 @@
+use std::collections::HashSet;
+
+use calamine::{DataType, Range, Reader};
+
+use super::column_finders::{
+    find_header_cols, find_header_row, header_text, MatchMethod, HEADER_SEARCH_ROWS,
+};
+use super::{
+    key_col, make_header, sheet_from_selector, ColIndexer, JoinKey, RowSource, Sheet,
+    SheetExtractor, SheetLayout, SheetSelector,
+};
+
+/// Check the invariants of an extractor definition that do not depend on a workbook.
+///
+/// Returns a description of every problem found; an empty vector means the definition is sound.
+pub fn check_structure(extractors: &[SheetExtractor]) -> Vec<String> {
+    let mut problems = vec![];
+    // output columns of the sheets checked so far, which a join key can take sample keys from
+    let mut earlier_cols: Vec<&str> = vec![];
+    for extractor in extractors.iter() {
+        match extractor {
+            SheetExtractor::Single(sheet) => {
+                problems.extend(check_join_key(sheet.sheet_name, &sheet.join_key, &earlier_cols));
+                problems.extend(check_patterns(
+                    &format!("Sheet '{}'", sheet.sheet_name),
+                    &sheet.col_indexers,
+                    sheet.join_key.as_ref().map(|k| &k.sheet_col),
+                    &sheet.layout,
+                ));
+                if sheet.col_names.len() != sheet.col_indexers.len() {
+                    problems.push(format!(
+                        "Sheet '{}' has {} column names but {} column indexers",
+                        sheet.sheet_name,
+                        sheet.col_names.len(),
+                        sheet.col_indexers.len()
+                    ));
+                }
+            }
+            SheetExtractor::Multi(selector) => {
+                for p_sheet in selector.potential_sheets.iter() {
+                    problems.extend(check_join_key(
+                        p_sheet.sheet_name,
+                        &selector.join_key,
+                        &earlier_cols,
+                    ));
+                    problems.extend(check_patterns(
+                        &format!("Potential sheet '{}'", p_sheet.sheet_name),
+                        &p_sheet.col_indexers,
+                        selector.join_key.as_ref().map(|k| &k.sheet_col),
+                        &p_sheet.layout,
+                    ));
+                }
+                for p_sheet in selector.potential_sheets.iter() {
+                    if selector.col_names.len() != p_sheet.col_indexers.len() {
+                        problems.push(format!(
+                            "Potential sheet '{}' (validated on '{}') has {} column indexers \
+                             but its selector has {} column names",
+                            p_sheet.sheet_name,
+                            p_sheet.sheet_for_val,
+                            p_sheet.col_indexers.len(),
+                            selector.col_names.len()
+                        ));
+                    }
+                }
+            }
+        }
+        earlier_cols.extend(make_header(std::slice::from_ref(extractor)));
+    }
+
+    let mut seen = HashSet::new();
+    for name in make_header(extractors) {
+        if !seen.insert(name) {
+            problems.push(format!("Output column '{}' is defined more than once", name));
+        }
+    }
+    problems
+}
+
+fn check_join_key(
+    sheet_name: &str,
+    join_key: &Option<JoinKey>,
+    earlier_cols: &[&str],
+) -> Vec<String> {
+    let join_key = match join_key {
+        Some(join_key) => join_key,
+        None => return vec![],
+    };
+    let mut problems = vec![];
+    if let ColIndexer::CellValue(..) | ColIndexer::DefaultValue(_) = join_key.sheet_col {
+        problems.push(format!(
+            "Sheet '{}' has a join key that is not a column index or header",
+            sheet_name
+        ));
+    }
+    if !earlier_cols.contains(&join_key.sample_col) {
+        problems.push(format!(
+            "Sheet '{}' joins on '{}', which no earlier sheet extracts",
+            sheet_name, join_key.sample_col
+        ));
+    }
+    problems
+}
+
+/// Report every header pattern of a sheet's columns, join key and expected headers that is not
+/// a valid regular expression.
+fn check_patterns(
+    context: &str,
+    indexers: &[ColIndexer],
+    join_key: Option<&ColIndexer>,
+    layout: &SheetLayout,
+) -> Vec<String> {
+    indexers
+        .iter()
+        .chain(join_key)
+        .filter_map(|indexer| match indexer {
+            ColIndexer::Header(match_method) => Some(match_method),
+            _ => None,
+        })
+        .chain(layout.expected_headers.iter())
+        .flat_map(MatchMethod::invalid_patterns)
+        .map(|problem| format!("{}: {}", context, problem))
+        .collect()
+}
+
+/// Check that an extractor declares at least one row source and that every key identifies a
+/// column.
+pub fn check_row_sources(sources: &[RowSource]) -> Vec<String> {
+    if sources.is_empty() {
+        return vec!["No row source is declared".to_string()];
+    }
+    sources
+        .iter()
+        .flat_map(|source| {
+            source.key_cols.iter().filter_map(move |indexer| match indexer {
+                ColIndexer::Index(_) | ColIndexer::Header(_) => None,
+                _ => Some(format!(
+                    "Row source '{}' has a key that is not a column index or header",
+                    source.sheet_name
+                )),
+            })
+            .chain(check_patterns(
+                &format!("Row source '{}'", source.sheet_name),
+                &source.key_cols,
+                None,
+                &source.layout,
+            ))
+        })
+        .collect()
+}
+
+/// Check that a sample workbook has a usable row source, reporting why each one is not.
+pub fn check_workbook_rows<R: Reader>(wb: &mut R, sources: &[RowSource]) -> Vec<String> {
+    let mut problems = vec![];
+    for source in sources.iter() {
+        let ws = match wb.worksheet_range(source.sheet_name) {
+            Some(Ok(ws)) => ws,
+            Some(Err(e)) => {
+                problems.push(format!(
+                    "Row source sheet '{}' could not be read: {:?}",
+                    source.sheet_name, e
+                ));
+                continue;
+            }
+            None => {
+                problems.push(format!("Row source sheet '{}' is missing", source.sheet_name));
+                continue;
+            }
+        };
+        let layout = source.layout.locate(&ws);
+        let missing: Vec<String> = source
+            .key_cols
+            .iter()
+            .filter(|indexer| key_col(&ws, indexer, &layout).is_none())
+            .map(|indexer| format!("{:?}", indexer))
+            .collect();
+        if missing.is_empty() {
+            return vec![];
+        }
+        problems.push(format!(
+            "Row source sheet '{}' has no key column for {}",
+            source.sheet_name,
+            missing.join(", ")
+        ));
+    }
+    problems
+}
+
+/// Run an extractor definition against a sample workbook, reporting every missing sheet
+/// and every column whose header could not be found.
+pub fn check_workbook<R: Reader>(wb: &mut R, extractors: &[SheetExtractor]) -> Vec<String> {
+    let mut problems = vec![];
+    for extractor in extractors.iter() {
+        match extractor {
+            SheetExtractor::Single(sheet) => problems.extend(check_sheet(wb, sheet)),
+            SheetExtractor::Multi(selector) => problems.extend(check_selector(wb, selector)),
+        }
+    }
+    problems
+}
+
+fn check_selector<R: Reader>(wb: &mut R, selector: &SheetSelector) -> Vec<String> {
+    if let Some(sheet) = sheet_from_selector(wb, selector) {
+        return check_sheet(wb, &sheet);
+    }
+    let tried: Vec<String> = selector
+        .potential_sheets
+        .iter()
+        .map(|p| format!("'{}'", p.sheet_for_val))
+        .collect();
+    vec![format!(
+        "No potential sheet validated for columns {:?} (validated against {})",
+        selector.col_names,
+        tried.join(", ")
+    )]
+}
+
+fn check_sheet<R: Reader>(wb: &mut R, sheet: &Sheet) -> Vec<String> {
+    let ws = match wb.worksheet_range(sheet.sheet_name) {
+        Some(Ok(ws)) => ws,
+        Some(Err(e)) => {
+            return vec![format!("Sheet '{}' could not be read: {:?}", sheet.sheet_name, e)]
+        }
+        None => return vec![format!("Missing sheet '{}'", sheet.sheet_name)],
+    };
+    let expected = &sheet.layout.expected_headers;
+    let header_row_problem = if !expected.is_empty()
+        && find_header_row(&ws, expected, sheet.layout.header_height).is_none()
+    {
+        Some(format!(
+            "Sheet '{}': no header row holding {:?} in the first {} rows",
+            sheet.sheet_name, expected, HEADER_SEARCH_ROWS
+        ))
+    } else {
+        None
+    };
+    let layout = sheet.layout.locate(&ws);
+    let join_key_problem = match &sheet.join_key {
+        Some(join_key) if key_col(&ws, &join_key.sheet_col, &layout).is_none() => Some(format!(
+            "Sheet '{}': no join key column {:?}, its rows are matched by position",
+            sheet.sheet_name, join_key.sheet_col
+        )),
+        _ => None,
+    };
+    sheet
+        .col_names
+        .iter()
+        .zip(sheet.col_indexers.iter())
+        .filter_map(|(name, indexer)| match indexer {
+            ColIndexer::Header(match_method) => match key_col(&ws, indexer, &layout) {
+                Some(_) => None,
+                None => Some(format!(
+                    "Sheet '{}': no header matching {:?} for column '{}'",
+                    sheet.sheet_name, match_method, name
+                )),
+            },
+            _ => None,
+        })
+        .chain(header_row_problem)
+        .chain(join_key_problem)
+        .chain(ambiguous_headers(&ws, sheet, &layout))
+        .collect()
+}
+
+/// Describe every column of `sheet` whose header matches more than one column of `ws`, laid
+/// out as `layout` locates it, which is extracted from the first of them.
+pub fn ambiguous_headers(ws: &Range<DataType>, sheet: &Sheet, layout: &SheetLayout) -> Vec<String> {
+    sheet
+        .col_names
+        .iter()
+        .zip(sheet.col_indexers.iter())
+        .filter_map(|(name, indexer)| {
+            let match_method = match indexer {
+                ColIndexer::Header(match_method) => match_method,
+                _ => return None,
+            };
+            let cols = find_header_cols(ws, match_method, layout.header_row, layout.header_height);
+            if cols.len() < 2 {
+                return None;
+            }
+            let headers: Vec<String> = cols
+                .iter()
+                .filter_map(|col| header_text(ws, *col, layout.header_row, layout.header_height))
+                .map(|header| format!("'{}'", header))
+                .collect();
+            Some(format!(
+                "Sheet '{}': {:?} for column '{}' matches {} headers, {}; the first is used",
+                sheet.sheet_name,
+                match_method,
+                name,
+                cols.len(),
+                headers.join(", ")
+            ))
+        })
+        .collect()
+}
+
+/// Describe which alias each column found by a list of aliases matched in a sample workbook.
+pub fn report_aliases<R: Reader>(wb: &mut R, extractors: &[SheetExtractor]) -> Vec<String> {
+    let mut report = vec![];
+    for extractor in extractors.iter() {
+        let sheet = match extractor {
+            SheetExtractor::Single(sheet) => sheet.clone(),
+            SheetExtractor::Multi(selector) => match sheet_from_selector(wb, selector) {
+                Some(sheet) => sheet,
+                None => continue,
+            },
+        };
+        let ws = match wb.worksheet_range(sheet.sheet_name) {
+            Some(Ok(ws)) => ws,
+            _ => continue,
+        };
+        let layout = sheet.layout.locate(&ws);
+        for (name, indexer) in sheet.col_names.iter().zip(sheet.col_indexers.iter()) {
+            let match_method = match indexer {
+                ColIndexer::Header(m @ MatchMethod::Aliases(_)) => m,
+                _ => continue,
+            };
+            let header = key_col(&ws, indexer, &layout)
+                .and_then(|col| header_text(&ws, col, layout.header_row, layout.header_height));
+            if let Some(header) = header {
+                if let Some(alias) = match_method.matching(&header) {
+                    report.push(format!(
+                        "Sheet '{}': column '{}' found by {:?} in header '{}'",
+                        sheet.sheet_name, name, alias, header
+                    ));
+                }
+            }
+        }
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+    use std::collections::HashMap;
+    use std::io::Cursor;
+
+    use calamine::vba::VbaProject;
+    use calamine::{Error, Metadata};
+
+    use super::*;
+
+    /// A workbook of in-memory sheets, a sheet named in `unreadable` failing to parse.
+    #[derive(Default)]
+    struct FakeWorkbook {
+        sheets: HashMap<&'static str, Range<DataType>>,
+        unreadable: Vec<&'static str>,
+        metadata: Metadata,
+    }
+
+    impl Reader for FakeWorkbook {
+        type RS = Cursor<Vec<u8>>;
+        type Error = Error;
+
+        fn new(_reader: Self::RS) -> Result<Self, Self::Error> {
+            Ok(FakeWorkbook::default())
+        }
+
+        fn vba_project(&mut self) -> Option<Result<Cow<'_, VbaProject>, Self::Error>> {
+            None
+        }
+
+        fn metadata(&self) -> &Metadata {
+            &self.metadata
+        }
+
+        fn worksheet_range(&mut self, name: &str) -> Option<Result<Range<DataType>, Error>> {
+            if self.unreadable.contains(&name) {
+                return Some(Err(Error::Msg("bad sheet xml")));
+            }
+            self.sheets.get(name).cloned().map(Ok)
+        }
+
+        fn worksheet_formula(&mut self, _name: &str) -> Option<Result<Range<String>, Error>> {
+            None
+        }
+    }
+
+    /// A sheet whose rows are given as strings, an empty string leaving its cell empty.
+    fn range(rows: &[&[&str]]) -> Range<DataType> {
+        let width = rows.iter().map(|r| r.len()).max().unwrap_or(1) as u32;
+        let mut ws = Range::new((0, 0), (rows.len() as u32 - 1, width - 1));
+        for (row, cells) in rows.iter().enumerate() {
+            for (col, cell) in cells.iter().enumerate() {
+                if !cell.is_empty() {
+                    ws.set_value((row as u32, col as u32), DataType::String(cell.to_string()));
+                }
+            }
+        }
+        ws
+    }
+
+    fn header(name: &'static str) -> ColIndexer {
+        ColIndexer::Header(MatchMethod::Exact(name))
+    }
+
+    fn sheet(sheet_name: &'static str, col_names: Vec<&'static str>) -> Sheet {
+        Sheet {
+            sheet_name,
+            col_indexers: col_names.iter().map(|n| header(n)).collect(),
+            col_names,
+            ..Default::default()
+        }
+    }
+
+    fn structure_problems(sheets: Vec<Sheet>) -> Vec<String> {
+        let extractors: Vec<SheetExtractor> =
+            sheets.into_iter().map(SheetExtractor::Single).collect();
+        check_structure(&extractors)
+    }
+
+    #[test]
+    fn sound_structure() {
+        let results = Sheet {
+            join_key: Some(JoinKey {
+                sheet_col: header("Sample"),
+                sample_col: "Sample Name",
+            }),
+            ..sheet("Sample Data", vec!["CBDa"])
+        };
+        assert!(structure_problems(vec![sheet("Master List", vec!["Sample Name"]), results])
+            .is_empty());
+    }
+
+    #[test]
+    fn column_count_mismatch() {
+        let mismatched = Sheet {
+            col_indexers: vec![header("Test ID")],
+            ..sheet("Master List", vec!["Test ID", "Sample Name"])
+        };
+        assert_eq!(
+            structure_problems(vec![mismatched]),
+            ["Sheet 'Master List' has 2 column names but 1 column indexers"]
+        );
+    }
+
+    #[test]
+    fn duplicate_output_column() {
+        let sheets = vec![sheet("Master List", vec!["Test ID"]), sheet("Results", vec!["Test ID"])];
+        assert_eq!(
+            structure_problems(sheets),
+            ["Output column 'Test ID' is defined more than once"]
+        );
+    }
+
+    #[test]
+    fn invalid_header_regex() {
+        let bad = Sheet {
+            col_indexers: vec![ColIndexer::Header(MatchMethod::regex("(unclosed"))],
+            ..sheet("Master List", vec!["Test ID"])
+        };
+        let problems = structure_problems(vec![bad]);
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].starts_with("Sheet 'Master List': Invalid header pattern '(unclosed'"));
+    }
+
+    #[test]
+    fn join_key_must_be_a_column() {
+        let not_columns = [ColIndexer::CellValue(0, 0), ColIndexer::DefaultValue(DataType::Empty)];
+        for sheet_col in not_columns {
+            let results = Sheet {
+                join_key: Some(JoinKey {
+                    sheet_col,
+                    sample_col: "Sample Name",
+                }),
+                ..sheet("Sample Data", vec!["CBDa"])
+            };
+            assert_eq!(
+                structure_problems(vec![sheet("Master List", vec!["Sample Name"]), results]),
+                ["Sheet 'Sample Data' has a join key that is not a column index or header"]
+            );
+        }
+    }
+
+    #[test]
+    fn join_key_sample_column_must_come_first() {
+        let join_key = Some(JoinKey {
+            sheet_col: header("Sample"),
+            sample_col: "Sample Name",
+        });
+        let results = Sheet {
+            join_key,
+            ..sheet("Sample Data", vec!["CBDa"])
+        };
+        // extracted, but only after the sheet joining on it
+        let sheets = vec![results, sheet("Master List", vec!["Sample Name"])];
+        assert_eq!(
+            structure_problems(sheets),
+            ["Sheet 'Sample Data' joins on 'Sample Name', which no earlier sheet extracts"]
+        );
+    }
+
+    #[test]
+    fn row_sources() {
+        assert_eq!(check_row_sources(&[]), ["No row source is declared"]);
+        let source = |key_cols| RowSource {
+            sheet_name: "Master List",
+            key_cols,
+            ..Default::default()
+        };
+        assert!(check_row_sources(&[source(vec![ColIndexer::Index(1), header("Test ID")])])
+            .is_empty());
+        assert_eq!(
+            check_row_sources(&[source(vec![ColIndexer::CellValue(0, 1)])]),
+            ["Row source 'Master List' has a key that is not a column index or header"]
+        );
+    }
+
+    #[test]
+    fn workbook_sheets_and_headers() {
+        let mut wb = FakeWorkbook {
+            sheets: vec![("Master List", range(&[&["Test ID"], &["T-1"]]))].into_iter().collect(),
+            unreadable: vec!["TYM Values"],
+            ..Default::default()
+        };
+        let extractors = [
+            SheetExtractor::Single(sheet("Master List", vec!["Test ID", "Sample Name"])),
+            SheetExtractor::Single(sheet("TYM Values", vec!["Colony Count"])),
+            SheetExtractor::Single(sheet("LOQ Summary", vec!["CBDa"])),
+        ];
+        assert_eq!(
+            check_workbook(&mut wb, &extractors),
+            [
+                "Sheet 'Master List': no header matching Exact(\"Sample Name\") for column \
+                 'Sample Name'",
+                "Sheet 'TYM Values' could not be read: Msg(\"bad sheet xml\")",
+                "Missing sheet 'LOQ Summary'",
+            ]
+        );
+    }
+
+    #[test]
+    fn workbook_row_sources() {
+        let mut wb = FakeWorkbook {
+            sheets: vec![("Master List", range(&[&["Test ID"], &["T-1"]]))].into_iter().collect(),
+            unreadable: vec!["Broken"],
+            ..Default::default()
+        };
+        let source = |sheet_name, key_cols| RowSource {
+            sheet_name,
+            key_cols,
+            ..Default::default()
+        };
+        assert_eq!(
+            check_workbook_rows(
+                &mut wb,
+                &[
+                    source("Broken", vec![ColIndexer::Index(0)]),
+                    source("Gone", vec![ColIndexer::Index(0)]),
+                    source("Master List", vec![header("Test Id")]),
+                ]
+            ),
+            [
+                "Row source sheet 'Broken' could not be read: Msg(\"bad sheet xml\")",
+                "Row source sheet 'Gone' is missing",
+                "Row source sheet 'Master List' has no key column for Header(Exact(\"Test Id\"))",
+            ]
+        );
+        // a usable source clears the problems of those before it
+        let sources = [
+            source("Gone", vec![ColIndexer::Index(0)]),
+            source("Master List", vec![header("Test ID")]),
+        ];
+        assert!(check_workbook_rows(&mut wb, &sources).is_empty());
+    }
+
+    #[test]
+    fn ambiguous_header_columns() {
+        let ws = range(&[&["MGK 264 10", "MGK 264 1", "Acephate"]]);
+        let starts_with = Sheet {
+            col_indexers: vec![
+                ColIndexer::Header(MatchMethod::StartsWith("MGK 264 1")),
+                ColIndexer::Header(MatchMethod::StartsWithWord("MGK 264 1")),
+                header("Acephate"),
+            ],
+            ..sheet("Sample Data", vec!["MGK", "MGK 264 1", "Acephate"])
+        };
+        assert_eq!(
+            ambiguous_headers(&ws, &starts_with, &starts_with.layout),
+            [
+                "Sheet 'Sample Data': StartsWith(\"MGK 264 1\") for column 'MGK' matches 2 \
+                 headers, 'MGK 264 10', 'MGK 264 1'; the first is used"
+            ]
+        );
+    }
+}
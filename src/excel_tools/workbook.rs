@@ -0,0 +1,147 @@
+use std::borrow::Cow;
+use std::io::Cursor;
+use std::path::Path;
+
+use calamine::vba::VbaProject;
+use calamine::{DataType, Error, Metadata, Range, Reader, Xls, Xlsb, Xlsx};
+
+/// A workbook of any cert generator format, read into memory so it can come from a file on
+/// disk or from inside a zip archive.
+pub enum Workbook {
+    Xls(Xls<Cursor<Vec<u8>>>),
+    Xlsx(Xlsx<Cursor<Vec<u8>>>),
+    Xlsb(Xlsb<Cursor<Vec<u8>>>),
+}
+
+impl Workbook {
+    /// Open a workbook's contents in the format they are in, or in the one its file name's
+    /// extension names when the contents do not tell.
+    pub fn from_bytes(path: &Path, bytes: Vec<u8>) -> Result<Self, Error> {
+        let format = format_of_contents(&bytes)
+            .or_else(|| format_of_extension(path))
+            .ok_or(Error::Msg("Unknown workbook format"))?;
+        let reader = Cursor::new(bytes);
+        Ok(match format {
+            Format::Xls => Workbook::Xls(Xls::new(reader).map_err(Error::Xls)?),
+            Format::Xlsx => Workbook::Xlsx(Xlsx::new(reader).map_err(Error::Xlsx)?),
+            Format::Xlsb => Workbook::Xlsb(Xlsb::new(reader).map_err(Error::Xlsb)?),
+        })
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Format {
+    Xls,
+    Xlsx,
+    Xlsb,
+}
+
+/// Signature of a compound file, which xls workbooks are stored as.
+const CFB_MAGIC: &[u8] = &[0xd0, 0xcf, 0x11, 0xe0, 0xa1, 0xb1, 0x1a, 0xe1];
+/// Signature of a zip archive, which xlsx and xlsb workbooks are stored as.
+const ZIP_MAGIC: &[u8] = b"PK\x03\x04";
+
+/// The format of a workbook's contents: xls for a compound file, and for a zip, xlsb when it
+/// holds a binary workbook part and xlsx otherwise.
+fn format_of_contents(bytes: &[u8]) -> Option<Format> {
+    if bytes.starts_with(CFB_MAGIC) {
+        return Some(Format::Xls);
+    }
+    if !bytes.starts_with(ZIP_MAGIC) {
+        return None;
+    }
+    let archive = zip::ZipArchive::new(Cursor::new(bytes)).ok()?;
+    if archive.file_names().any(|name| name == "xl/workbook.bin") {
+        Some(Format::Xlsb)
+    } else {
+        Some(Format::Xlsx)
+    }
+}
+
+fn format_of_extension(path: &Path) -> Option<Format> {
+    let extension = path.extension()?.to_str()?.to_lowercase();
+    match extension.as_str() {
+        "xls" | "xla" => Some(Format::Xls),
+        "xlsx" | "xlsm" | "xlam" => Some(Format::Xlsx),
+        "xlsb" => Some(Format::Xlsb),
+        _ => None,
+    }
+}
+
+impl Reader for Workbook {
+    type RS = Cursor<Vec<u8>>;
+    type Error = Error;
+
+    fn new(_reader: Self::RS) -> Result<Self, Self::Error> {
+        Err(Error::Msg("Workbook must be created with from_bytes"))
+    }
+
+    fn vba_project(&mut self) -> Option<Result<Cow<'_, VbaProject>, Self::Error>> {
+        match self {
+            Workbook::Xls(wb) => wb.vba_project().map(|vba| vba.map_err(Error::Xls)),
+            Workbook::Xlsx(wb) => wb.vba_project().map(|vba| vba.map_err(Error::Xlsx)),
+            Workbook::Xlsb(wb) => wb.vba_project().map(|vba| vba.map_err(Error::Xlsb)),
+        }
+    }
+
+    fn metadata(&self) -> &Metadata {
+        match self {
+            Workbook::Xls(wb) => wb.metadata(),
+            Workbook::Xlsx(wb) => wb.metadata(),
+            Workbook::Xlsb(wb) => wb.metadata(),
+        }
+    }
+
+    fn worksheet_range(&mut self, name: &str) -> Option<Result<Range<DataType>, Self::Error>> {
+        match self {
+            Workbook::Xls(wb) => wb.worksheet_range(name).map(|r| r.map_err(Error::Xls)),
+            Workbook::Xlsx(wb) => wb.worksheet_range(name).map(|r| r.map_err(Error::Xlsx)),
+            Workbook::Xlsb(wb) => wb.worksheet_range(name).map(|r| r.map_err(Error::Xlsb)),
+        }
+    }
+
+    fn worksheet_formula(&mut self, name: &str) -> Option<Result<Range<String>, Self::Error>> {
+        match self {
+            Workbook::Xls(wb) => wb.worksheet_formula(name).map(|r| r.map_err(Error::Xls)),
+            Workbook::Xlsx(wb) => wb.worksheet_formula(name).map(|r| r.map_err(Error::Xlsx)),
+            Workbook::Xlsb(wb) => wb.worksheet_formula(name).map(|r| r.map_err(Error::Xlsb)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    fn zip_holding(name: &str) -> Vec<u8> {
+        let mut zip = zip::ZipWriter::new(Cursor::new(vec![]));
+        zip.start_file(name, Default::default()).unwrap();
+        zip.write_all(b"<workbook/>").unwrap();
+        zip.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn format_is_read_from_the_contents() {
+        let mut cfb = CFB_MAGIC.to_vec();
+        cfb.extend([0; 8]);
+        assert_eq!(format_of_contents(&cfb), Some(Format::Xls));
+        assert_eq!(format_of_contents(&zip_holding("xl/workbook.xml")), Some(Format::Xlsx));
+        assert_eq!(format_of_contents(&zip_holding("xl/workbook.bin")), Some(Format::Xlsb));
+        assert_eq!(format_of_contents(b"Test ID,Sample Name"), None);
+        // a truncated zip is left to the extension
+        assert_eq!(format_of_contents(ZIP_MAGIC), None);
+    }
+
+    #[test]
+    fn extension_is_the_fallback() {
+        let format = |name| format_of_extension(Path::new(name));
+        assert_eq!(format("a.XLSM"), Some(Format::Xlsx));
+        assert_eq!(format("a.xls"), Some(Format::Xls));
+        assert_eq!(format("a.xlsb"), Some(Format::Xlsb));
+        assert_eq!(format("a.csv"), None);
+        assert_eq!(format("xlsx"), None);
+        assert!(Workbook::from_bytes(Path::new("a.csv"), b"Test ID".to_vec()).is_err());
+    }
+}
@@ -1,18 +1,16 @@
-use calamine::{DataType, Range};
-use std::rc::Rc;
+use crate::excel_tools::column_finders::MatchMethod;
+use super::{master_list_layout, sample_name_header, test_id_header, Extractor};
+use crate::excel_tools::{ColIndexer, RowSource, Sheet, SheetExtractor};
+use crate::transforms::{Analyte, CfuCheck, UnitSource, UnitSpec};
 
-use crate::excel_tools::column_finders::{header_match, MatchMethod};
-use crate::excel_tools::{ColIndexer, Sheet, SheetExtractor};
+/// How the current template spells the sample name header.
+const SAMPLE_NAME: &str = "SAMPLE NAME";
 
-fn header_starts_with(
-    starts_with: &'static str,
-) -> Rc<dyn Fn(&Range<DataType>, u32) -> Range<DataType>> {
-    Rc::new(move |ws: &Range<DataType>, row_count: u32| {
-        header_match(ws, MatchMethod::StartsWith(starts_with), 0, row_count, None)
-    })
+fn header_starts_with(starts_with: &'static str) -> ColIndexer {
+    ColIndexer::Header(MatchMethod::StartsWith(starts_with))
 }
 
-pub fn get_extractors() -> Vec<SheetExtractor> {
+pub fn get_extractor() -> Extractor {
     let master_list = SheetExtractor::Single(Sheet {
         sheet_name: "Master List",
         col_names: vec![
@@ -23,12 +21,14 @@ pub fn get_extractors() -> Vec<SheetExtractor> {
             "Sample Type",
         ],
         col_indexers: vec![
-            ColIndexer::ColFindFunc(header_starts_with("Test ID")),
-            ColIndexer::ColFindFunc(header_starts_with("LICENSE NAME")),
-            ColIndexer::ColFindFunc(header_starts_with("CUSTOMER LICENSE")),
-            ColIndexer::ColFindFunc(header_starts_with("SAMPLE NAME")),
-            ColIndexer::ColFindFunc(header_starts_with("SAMPLE TYPE")),
+            ColIndexer::Header(test_id_header()),
+            header_starts_with("LICENSE NAME"),
+            header_starts_with("CUSTOMER LICENSE"),
+            ColIndexer::Header(sample_name_header(SAMPLE_NAME)),
+            header_starts_with("SAMPLE TYPE"),
         ],
+        layout: master_list_layout(vec![test_id_header(), sample_name_header(SAMPLE_NAME)]),
+        ..Default::default()
     });
 
     let tym_sheet = SheetExtractor::Single(Sheet {
@@ -41,13 +41,48 @@ pub fn get_extractors() -> Vec<SheetExtractor> {
             "Reported CFU/g",
         ],
         col_indexers: vec![
-            ColIndexer::ColFindFunc(header_starts_with("TYM Sample Weight")),
-            ColIndexer::ColFindFunc(header_starts_with("TYM Diluent Vol")),
-            ColIndexer::ColFindFunc(header_starts_with("Colony Count")),
-            ColIndexer::ColFindFunc(header_starts_with("Dilution Plate")),
-            ColIndexer::ColFindFunc(header_starts_with("Metrc Reported CFU")),
+            header_starts_with("TYM Sample Weight"),
+            header_starts_with("TYM Diluent Vol"),
+            header_starts_with("Colony Count"),
+            header_starts_with("Dilution Plate"),
+            header_starts_with("Metrc Reported CFU"),
         ],
+        ..Default::default()
     });
 
-    vec![master_list, tym_sheet]
+    Extractor {
+        version: 5,
+        sheets: vec![master_list, tym_sheet],
+        row_sources: vec![
+            RowSource {
+                sheet_name: "Master List",
+                key_cols: vec![ColIndexer::Header(test_id_header())],
+                layout: master_list_layout(vec![test_id_header(), sample_name_header(SAMPLE_NAME)]),
+                ..Default::default()
+            },
+            // templates from before Test IDs were assigned
+            RowSource {
+                sheet_name: "Master List",
+                key_cols: vec![ColIndexer::Header(sample_name_header(SAMPLE_NAME))],
+                layout: master_list_layout(vec![sample_name_header(SAMPLE_NAME)]),
+                ..Default::default()
+            },
+        ],
+        test_id: "Test ID",
+        sample_name: "Sample Name",
+        sample_type: Some("Sample Type"),
+        analytes: vec![Analyte::named("Reported CFU/g", "Total Yeast and Mold")],
+        units: Some(UnitSpec {
+            source: UnitSource::Fixed("CFU/g"),
+            canonical: "CFU/g",
+        }),
+        qualified_results: vec!["Colony Count", "Reported CFU/g"],
+        cfu_checks: vec![CfuCheck {
+            colony_count: "Colony Count",
+            dilution_plate: "Dilution Plate",
+            sample_weight: "Sample Weight (g)",
+            diluent_volume: "Diluent Vol (mL)",
+            reported: "Reported CFU/g",
+        }],
+    }
 }
@@ -1,22 +1,33 @@
-use calamine::{DataType, Range};
-use std::rc::Rc;
+use crate::excel_tools::column_finders::MatchMethod;
+use super::{master_list_layout, sample_name_header, test_id_header, Extractor};
+use crate::excel_tools::{ColIndexer, ColType, JoinKey, RowSource, Sheet, SheetExtractor};
+use crate::transforms::{Analyte, UnitSource, UnitSpec};
 
-use crate::excel_tools::column_finders::{header_match, MatchMethod};
-use crate::excel_tools::{ColIndexer, Sheet, SheetExtractor};
+/// How the current template spells the sample name header.
+const SAMPLE_NAME: &str = "Sample Name";
 
-fn find_col(starts_with: &'static str) -> Rc<dyn Fn(&Range<DataType>, u32) -> Range<DataType>> {
-    Rc::new(move |ws: &Range<DataType>, row_count: u32| {
-        header_match(ws, MatchMethod::StartsWith(starts_with), 0, row_count, None)
-    })
+fn find_col(starts_with: &'static str) -> ColIndexer {
+    ColIndexer::Header(MatchMethod::StartsWith(starts_with))
 }
 
-fn header_exact(match_str: &'static str) -> Rc<dyn Fn(&Range<DataType>, u32) -> Range<DataType>> {
-    Rc::new(move |ws: &Range<DataType>, row_count: u32| {
-        header_match(ws, MatchMethod::Exact(match_str), 0, row_count, None)
-    })
+fn header_exact(match_str: &'static str) -> ColIndexer {
+    ColIndexer::Header(MatchMethod::Exact(match_str))
 }
 
-pub fn get_extractors() -> Vec<SheetExtractor> {
+/// Sample Data and LOQ Summary rows are matched to samples by name, since they can be sorted
+/// differently from the Master List. Older templates without a sample column are matched by
+/// position.
+fn sample_join_key() -> JoinKey {
+    JoinKey {
+        sheet_col: ColIndexer::Header(MatchMethod::Aliases(vec![
+            MatchMethod::CaseInsensitive("Sample"),
+            sample_name_header(SAMPLE_NAME),
+        ])),
+        sample_col: "Sample Name",
+    }
+}
+
+pub fn get_extractor() -> Extractor {
     let master_list = SheetExtractor::Single(Sheet {
         sheet_name: "Master List",
         col_names: vec![
@@ -28,32 +39,47 @@ pub fn get_extractors() -> Vec<SheetExtractor> {
             "Sample Type"
         ],
         col_indexers: vec![
-            ColIndexer::ColFindFunc(find_col("Test ID")),
-            ColIndexer::ColFindFunc(find_col("Customer License Name")),
-            ColIndexer::ColFindFunc(find_col("Customer License Number")),
-            ColIndexer::ColFindFunc(find_col("Manifest")),
-            ColIndexer::ColFindFunc(find_col("Sample Name")),
-            ColIndexer::ColFindFunc(find_col("Type")),
-        ]
+            ColIndexer::Header(test_id_header()),
+            find_col("Customer License Name"),
+            find_col("Customer License Number"),
+            ColIndexer::Header(MatchMethod::Contains("Manifest")),
+            ColIndexer::Header(sample_name_header(SAMPLE_NAME)),
+            ColIndexer::Header(MatchMethod::Aliases(vec![
+                MatchMethod::StartsWith("Type"),
+                MatchMethod::StartsWith("Sample Type"),
+            ])),
+        ],
+        col_types: vec![("Manifest", ColType::Text)],
+        layout: master_list_layout(vec![test_id_header(), sample_name_header(SAMPLE_NAME)]),
+        ..Default::default()
     });
     // TODO: add the other analytes here someday
     let results = SheetExtractor::Single(Sheet {
         sheet_name: "Sample Data",
-        col_names: vec!["CBDa", "CBDVa", "CBDV"],
+        col_names: vec!["Unit", "CBDa", "CBDVa", "CBDV"],
         col_indexers: vec![
-            ColIndexer::ColFindFunc(header_exact("CBDa")),
-            ColIndexer::ColFindFunc(header_exact("CBDVa")),
-            ColIndexer::ColFindFunc(header_exact("CBDV")),
-        ]
+            // results are % in some templates and mg/g in others
+            ColIndexer::Header(MatchMethod::Aliases(vec![
+                MatchMethod::CaseInsensitive("Unit"),
+                MatchMethod::CaseInsensitive("Units"),
+            ])),
+            header_exact("CBDa"),
+            header_exact("CBDVa"),
+            header_exact("CBDV"),
+        ],
+        join_key: Some(sample_join_key()),
+        ..Default::default()
     });
     let limit_of_quants = SheetExtractor::Single(Sheet {
         sheet_name: "LOQ Summary",
         col_names: vec!["CBDa LLOQ", "CBDVa LLOQ", "CBDV LLOQ"],
         col_indexers: vec![
-            ColIndexer::ColFindFunc(header_exact("CBDa")),
-            ColIndexer::ColFindFunc(header_exact("CBDVa")),
-            ColIndexer::ColFindFunc(header_exact("CBDV")),
-        ]
+            header_exact("CBDa"),
+            header_exact("CBDVa"),
+            header_exact("CBDV"),
+        ],
+        join_key: Some(sample_join_key()),
+        ..Default::default()
     });
     let sample_prep = SheetExtractor::Single(Sheet {
         sheet_name: "Sample Prep Form",
@@ -61,8 +87,55 @@ pub fn get_extractors() -> Vec<SheetExtractor> {
         col_indexers: vec![
             ColIndexer::CellValue(7, 1),
             ColIndexer::CellValue(0, 4),
-        ]
+        ],
+        col_types: vec![("Start Date", ColType::Date)],
+        ..Default::default()
     });
 
-    vec![master_list, results, limit_of_quants, sample_prep]
-}
\ No newline at end of file
+    Extractor {
+        version: 7,
+        sheets: vec![master_list, results, limit_of_quants, sample_prep],
+        row_sources: vec![
+            RowSource {
+                sheet_name: "Master List",
+                key_cols: vec![ColIndexer::Header(test_id_header())],
+                layout: master_list_layout(vec![test_id_header(), sample_name_header(SAMPLE_NAME)]),
+                ..Default::default()
+            },
+            // templates from before Test IDs were assigned
+            RowSource {
+                sheet_name: "Master List",
+                key_cols: vec![ColIndexer::Header(sample_name_header(SAMPLE_NAME))],
+                layout: master_list_layout(vec![sample_name_header(SAMPLE_NAME)]),
+                ..Default::default()
+            },
+        ],
+        test_id: "Test ID",
+        sample_name: "Sample Name",
+        sample_type: Some("Sample Type"),
+        analytes: vec![
+            Analyte {
+                result: "CBDa",
+                lloq: Some("CBDa LLOQ"),
+                ..Default::default()
+            },
+            Analyte {
+                result: "CBDVa",
+                lloq: Some("CBDVa LLOQ"),
+                ..Default::default()
+            },
+            Analyte {
+                result: "CBDV",
+                lloq: Some("CBDV LLOQ"),
+                ..Default::default()
+            },
+        ],
+        // templates without a unit column are left unconverted
+        units: Some(UnitSpec {
+            source: UnitSource::Column("Unit"),
+            canonical: "%",
+        }),
+        qualified_results: vec!["CBDa", "CBDVa", "CBDV"],
+        cfu_checks: vec![],
+    }
+}
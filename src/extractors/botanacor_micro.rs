@@ -1,34 +1,45 @@
 use calamine::{DataType, Range};
 
-use crate::excel_tools::{ColIndexer, PotentialSheet, Sheet, SheetExtractor, SheetSelector};
+use super::Extractor;
+use crate::excel_tools::{
+    ColIndexer, PotentialSheet, RowSource, Sheet, SheetExtractor, SheetLayout,
+    SheetSelector,
+};
+use crate::transforms::{Analyte, CfuCheck, UnitSource, UnitSpec};
 
-pub fn get_extractors() -> Vec<SheetExtractor> {
+/// Check of a reported CFU/g against its plate count, plated from the sample's PBST dilution.
+fn pbst_cfu_check(
+    colony_count: &'static str,
+    dilution_plate: &'static str,
+    reported: &'static str,
+) -> CfuCheck {
+    CfuCheck {
+        colony_count,
+        dilution_plate,
+        sample_weight: "PBST wt (g)",
+        diluent_volume: "PBST vol (mL)",
+        reported,
+    }
+}
+
+pub fn get_extractor() -> Extractor {
     let mid_2020_validator = ("AgrBotMap", |ws: &Range<DataType>| {
-        if let Some(dt) = ws.get_value((0, 3)) {
-            match dt {
-                DataType::String(s) => s == &"AgricorSampleName".to_string(),
-                _ => false,
-            }
+        if let Some(DataType::String(s)) = ws.get_value((0, 3)) {
+            s == "AgricorSampleName"
         } else {
             false
         }
     });
     let early_2020_validator = ("Master List", |ws: &Range<DataType>| {
-        if let Some(dt) = ws.get_value((0, 1)) {
-            match dt {
-                DataType::String(s) => s == &"Test Id".to_string(),
-                _ => false,
-            }
+        if let Some(DataType::String(s)) = ws.get_value((0, 1)) {
+            s == "Test Id"
         } else {
             false
         }
     });
     let pre_test_id_validator = ("Master List", |ws: &Range<DataType>| {
-        if let Some(dt) = ws.get_value((0, 1)) {
-            match dt {
-                DataType::String(s) => s.starts_with("Company Name"),
-                _ => false,
-            }
+        if let Some(DataType::String(s)) = ws.get_value((0, 1)) {
+            s.starts_with("Company Name")
         } else {
             false
         }
@@ -42,20 +53,24 @@ pub fn get_extractors() -> Vec<SheetExtractor> {
                 col_indexers: vec![ColIndexer::Index(3)],
                 sheet_for_val: mid_2020_validator.0,
                 validator: mid_2020_validator.1,
+                layout: SheetLayout::default(),
             },
             PotentialSheet {
                 sheet_name: "Master List",
                 col_indexers: vec![ColIndexer::Index(1)],
                 sheet_for_val: early_2020_validator.0,
                 validator: early_2020_validator.1,
+                layout: SheetLayout::default(),
             },
             PotentialSheet {
                 sheet_name: "Master List",
                 col_indexers: vec![ColIndexer::DefaultValue(DataType::String("NA".to_string()))],
                 sheet_for_val: pre_test_id_validator.0,
                 validator: pre_test_id_validator.1,
+                layout: SheetLayout::default(),
             },
         ],
+        ..Default::default()
     });
 
     let sample_info_sheet = SheetExtractor::Multi(SheetSelector {
@@ -82,6 +97,7 @@ pub fn get_extractors() -> Vec<SheetExtractor> {
                 ],
                 sheet_for_val: early_2020_validator.0,
                 validator: early_2020_validator.1,
+                layout: SheetLayout::default(),
             },
             PotentialSheet {
                 sheet_name: "Master List",
@@ -96,8 +112,10 @@ pub fn get_extractors() -> Vec<SheetExtractor> {
                 ],
                 sheet_for_val: pre_test_id_validator.0,
                 validator: pre_test_id_validator.1,
+                layout: SheetLayout::default(),
             },
         ],
+        ..Default::default()
     });
 
     let tym_values = SheetExtractor::Single(Sheet {
@@ -108,6 +126,7 @@ pub fn get_extractors() -> Vec<SheetExtractor> {
             ColIndexer::Index(5),
             ColIndexer::Index(6),
         ],
+        ..Default::default()
     });
     let tot_aerobic_values = SheetExtractor::Single(Sheet {
         sheet_name: "Total Aerobic",
@@ -117,6 +136,7 @@ pub fn get_extractors() -> Vec<SheetExtractor> {
             ColIndexer::Index(5),
             ColIndexer::Index(6),
         ],
+        ..Default::default()
     });
     let tot_col_values = SheetExtractor::Single(Sheet {
         sheet_name: "Total Coliforms",
@@ -130,13 +150,51 @@ pub fn get_extractors() -> Vec<SheetExtractor> {
             ColIndexer::Index(5),
             ColIndexer::Index(6),
         ],
+        ..Default::default()
     });
 
-    vec![
-        test_id_sheet,
-        sample_info_sheet,
-        tym_values,
-        tot_aerobic_values,
-        tot_col_values,
-    ]
+    Extractor {
+        version: 3,
+        sheets: vec![
+            test_id_sheet,
+            sample_info_sheet,
+            tym_values,
+            tot_aerobic_values,
+            tot_col_values,
+        ],
+        row_sources: vec![RowSource {
+            sheet_name: "Master List",
+            key_cols: vec![ColIndexer::Index(1)],
+            ..Default::default()
+        }],
+        test_id: "Test Id",
+        sample_name: "Sample Name",
+        sample_type: Some("Sample Type"),
+        analytes: vec![
+            Analyte::named("TYM Reported CFU/g", "Total Yeast and Mold"),
+            Analyte::named("TA Reported CFU/g", "Total Aerobic Count"),
+            Analyte::named("Coliforms Reported CFU/g", "Total Coliforms"),
+        ],
+        units: Some(UnitSpec {
+            source: UnitSource::Fixed("CFU/g"),
+            canonical: "CFU/g",
+        }),
+        qualified_results: vec![
+            "TYM CFU Count",
+            "TYM Reported CFU/g",
+            "TA CFU Count",
+            "TA Reported CFU/g",
+            "Coliforms CFU Count",
+            "Coliforms Reported CFU/g",
+        ],
+        cfu_checks: vec![
+            pbst_cfu_check("TYM CFU Count", "TYM Dil. Plate", "TYM Reported CFU/g"),
+            pbst_cfu_check("TA CFU Count", "TA Dil. Plate", "TA Reported CFU/g"),
+            pbst_cfu_check(
+                "Coliforms CFU Count",
+                "Coliforms Dil. Plate",
+                "Coliforms Reported CFU/g",
+            ),
+        ],
+    }
 }
@@ -3,21 +3,126 @@ mod botanacor_pesticides;
 mod agricor_micro;
 mod agricor_potency;
 
-use crate::excel_tools::SheetExtractor;
-
-pub fn get_extractors(extractors_name: &str) -> Result<Vec<SheetExtractor>, String> {
-    match extractors_name {
-        // Botanacor files
-        "botanacor_micro" => Ok(botanacor_micro::get_extractors()),
-        "botanacor_pesticides" => Ok(botanacor_pesticides::get_extractors()),
-
-        // Agricor files
-        "agricor_micro" => Ok(agricor_micro::get_extractors()),
-        "agricor_potency" => Ok(agricor_potency::get_extractors()),
-        // No match
-        _ => Err(format!(
-            "Could not find data extraction configuration for {}",
-            extractors_name
-        )),
+use crate::excel_tools::column_finders::MatchMethod;
+use crate::excel_tools::{
+    check_row_sources, check_structure, RowSource, SheetExtractor, SheetLayout,
+};
+use crate::transforms::{Analyte, CfuCheck, UnitSpec};
+
+/// Everything needed to pull one test type's data out of a cert generator workbook.
+pub struct Extractor {
+    /// Bumped whenever a change to the definition alters its output, so files extracted by an
+    /// earlier version are extracted again on incremental runs.
+    pub version: u32,
+    pub sheets: Vec<SheetExtractor>,
+    /// Where to find which rows hold samples, tried in order until one fits the workbook.
+    pub row_sources: Vec<RowSource>,
+    /// Output column holding the Test ID that identifies each sample.
+    pub test_id: &'static str,
+    /// Output column holding each sample's name.
+    pub sample_name: &'static str,
+    /// Output column holding each sample's type, which selects its action limits.
+    pub sample_type: Option<&'static str>,
+    /// Analyte results and the limits they are reported with.
+    pub analytes: Vec<Analyte>,
+    /// Unit of the analyte results, if known.
+    pub units: Option<UnitSpec>,
+    /// Output columns whose cells may hold qualified results such as "ND" or "< 10".
+    pub qualified_results: Vec<&'static str>,
+    /// Reported CFU/g results to recompute from their plate counts as a check on the
+    /// certificate.
+    pub cfu_checks: Vec<CfuCheck>,
+}
+
+/// Test ID header of an Agricor Master List, however it is capitalised and spaced.
+fn test_id_header() -> MatchMethod {
+    MatchMethod::regex(r"(?i)^test\s*id")
+}
+
+/// Sample name header of an Agricor Master List, spelled `spelling` in the template's current
+/// version. Older versions write it in another case or with other spacing.
+fn sample_name_header(spelling: &'static str) -> MatchMethod {
+    MatchMethod::Aliases(vec![
+        MatchMethod::StartsWith(spelling),
+        MatchMethod::NormalizedWhitespace(spelling),
+        MatchMethod::CaseInsensitive(spelling),
+    ])
+}
+
+/// Layout of a Master List whose header row is found by the headers in `expected`, so title rows
+/// added above it do not break extraction.
+fn master_list_layout(expected: Vec<MatchMethod>) -> SheetLayout {
+    SheetLayout {
+        expected_headers: expected,
+        ..Default::default()
+    }
+}
+
+/// Builds an extractor configuration.
+type ExtractorFn = fn() -> Extractor;
+
+/// Every registered extractor configuration, by name.
+pub const EXTRACTORS: &[(&str, ExtractorFn)] = &[
+    // Botanacor files
+    ("botanacor_micro", botanacor_micro::get_extractor),
+    ("botanacor_pesticides", botanacor_pesticides::get_extractor),
+    // Agricor files
+    ("agricor_micro", agricor_micro::get_extractor),
+    ("agricor_potency", agricor_potency::get_extractor),
+];
+
+/// Names of every registered extractor configuration.
+pub fn extractor_names() -> impl Iterator<Item = &'static str> {
+    EXTRACTORS.iter().map(|(name, _)| *name)
+}
+
+pub fn get_extractor(extractors_name: &str) -> Result<Extractor, String> {
+    EXTRACTORS
+        .iter()
+        .find(|(name, _)| *name == extractors_name)
+        .map(|(_, get)| get())
+        .ok_or_else(|| {
+            format!(
+                "Could not find data extraction configuration for {}",
+                extractors_name
+            )
+        })
+}
+
+/// Check the structure of every registered extractor, so a definition whose column names and
+/// indexers do not line up is caught before any file is processed.
+pub fn validate_registered() -> Result<(), String> {
+    let mut problems = vec![];
+    for (name, get) in EXTRACTORS.iter() {
+        let extractor = get();
+        problems.extend(
+            check_structure(&extractor.sheets)
+                .into_iter()
+                .chain(check_row_sources(&extractor.row_sources))
+                .map(|p| format!("{}: {}", name, p)),
+        );
+    }
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("Invalid extractor definitions: {}", problems.join("; ")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn registered_extractors_are_valid() {
+        assert!(validate_registered().is_ok());
+    }
+
+    #[test]
+    fn extractors_are_found_by_name() {
+        for name in extractor_names() {
+            assert!(get_extractor(name).is_ok(), "{}", name);
+        }
+        assert!(get_extractor("botanacor_potency").is_err());
     }
 }
@@ -1,51 +1,223 @@
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::error::Error;
-use std::fs;
 use std::fs::File;
 use std::io;
-use std::io::{BufWriter, Write};
+use std::io::{BufWriter, Cursor, Write};
 use std::path::{Path, PathBuf};
+use std::thread;
 
-use calamine::{open_workbook, DataType, Reader, Xlsx};
-use regex::{Regex, RegexBuilder};
+use calamine::{DataType, Reader};
 
+mod dates;
+mod discovery;
 mod excel_tools;
 mod extractors;
+mod manifest;
+mod options;
+#[cfg(test)]
+mod test_utils;
+mod transforms;
+mod util;
 
-use extractors::get_extractors;
-
-const DAY_DIR_REGEX: &str = r"\d{2}-[[:alpha:]]{3}-\d{4}";
+use discovery::{
+    detect_test_type, find_duplicate_rows, get_regex, has_lock_file, CgFile, Exclusions, Filters,
+};
+use dates::{Date, DateRange, DateSystem};
+use excel_tools::{Sheet, SourceRows, Workbook, MAX_ROWS};
+use extractors::{get_extractor, Extractor};
+use manifest::Manifest;
+use options::Options;
 
 fn main() -> Result<(), Box<dyn Error>> {
     // setup variables
     let args: Vec<String> = std::env::args().collect();
-    println!("Searching in: {}", &args[1]);
-    let root_path = Path::new(&args[1]).to_path_buf();
-    let company_param = args[2].to_string();
-    let test_name_param = args[3].to_string();
-    let years_regex_str =
-        parse_range_arg(args[4].to_string()).expect("Could not parse years parameter");
-    let months_regex_str =
-        parse_range_arg(args[5].to_string()).expect("Could not parse months parameter");
-    let test_type: String = vec![company_param, test_name_param].join("_");
+    if args.len() > 1 && args[1] == "check-config" {
+        return check_config(&args[2..]);
+    }
+    extractors::validate_registered()?;
+    if args.len() > 1 && args[1] == "watch" {
+        return watch(&args[2..]);
+    }
+
+    let options = options::parse_options(&args[1..])?;
+    let work = if options.files.is_empty() {
+        discover_files(&options)?
+    } else {
+        group_listed_files(&options.files)?
+    };
+    let work = exclude_files(work, &options.exclusions);
+    for (test_type, cg_files) in work.iter() {
+        extract_test_type(test_type, cg_files, &options)?;
+    }
+
+    println!("\n\nPress ENTER key to exit ...\n");
+    io::stdin().read_line(&mut String::new())?;
+
+    Ok(())
+}
+
+/// The files to extract, grouped by test type.
+type TestTypeFiles = Vec<(String, Vec<CgFile>)>;
+
+/// Find the files of the test type named by the positional arguments.
+fn discover_files(options: &Options) -> Result<TestTypeFiles, Box<dyn Error>> {
+    if options.positional.len() != 3 {
+        return Err("Usage: <root dir> <company> <test name> \
+                    [--from YYYY-MM-DD] [--to YYYY-MM-DD] [--last 30d] [--month YYYY-MM] \
+                    [--month-dirs number|number-name|name|any] [--layout <template>] [--full] \
+                    [--long] [--limits <limits table> --jurisdiction <name>] \
+                    [--cfu-tolerance <percent>] \
+                    [--exclude <glob>] [--no-default-excludes], \
+                    or: --files <workbook>... | --files-from <list file or ->"
+            .into());
+    }
+    let args = &options.positional;
+
+    println!("Searching in: {}", &args[0]);
+    let root_path = Path::new(&args[0]).to_path_buf();
+    let company_param = args[1].to_string();
+    let test_name_param = args[2].to_string();
+    let test_type: String = [company_param, test_name_param].join("_");
 
     let test_type_regex =
-        get_regex(test_type.as_str()).expect("Could not find regex for test type");
+        get_regex(test_type.as_str()).expect("Could not find regex for test type")?;
 
-    let cg_files = find_cg_files(
+    let cg_files = options.layout.find_files(
         &root_path,
-        &test_type_regex,
-        years_regex_str,
-        months_regex_str,
+        &Filters {
+            test_type: &test_type_regex,
+            date_range: &options.date_range,
+            month_dir_style: options.month_dir_style,
+        },
     );
-    println!("Files to be processed: {}", cg_files.len());
+    Ok(vec![(test_type, cg_files)])
+}
 
-    let extractors = get_extractors(&test_type)?;
-    let mut output_data = vec![];
-    for file in cg_files.iter() {
+/// Drop files matching the exclusion patterns, reporting each one.
+fn exclude_files(work: TestTypeFiles, exclusions: &Exclusions) -> TestTypeFiles {
+    let mut excluded = vec![];
+    let work = work
+        .into_iter()
+        .map(|(test_type, cg_files)| {
+            let (kept, dropped) = exclusions.apply(cg_files);
+            excluded.extend(dropped);
+            (test_type, kept)
+        })
+        .collect();
+    report_excluded(&excluded);
+    work
+}
+
+fn report_excluded(excluded: &[(PathBuf, String)]) {
+    if excluded.is_empty() {
+        return;
+    }
+    println!("Excluded {} file(s):", excluded.len());
+    for (path, reason) in excluded.iter() {
+        println!("  {}: {}", path.display(), reason);
+    }
+}
+
+/// Group explicitly listed workbooks by the test type their file names match.
+fn group_listed_files(files: &[PathBuf]) -> Result<TestTypeFiles, Box<dyn Error>> {
+    if let Some(missing) = files.iter().find(|f| !discovery::cg_file_exists(f)) {
+        return Err(format!("Listed file does not exist: {}", missing.display()).into());
+    }
+    let mut groups: BTreeMap<&str, Vec<CgFile>> = BTreeMap::new();
+    let mut unmatched = vec![];
+    for file in files.iter() {
+        match detect_test_type(file) {
+            Some((test_type, regex)) => groups
+                .entry(test_type)
+                .or_default()
+                .push(CgFile::from_listed_path(file.clone(), &regex)),
+            None => unmatched.push(file.display().to_string()),
+        }
+    }
+    if !unmatched.is_empty() {
+        return Err(format!("Could not detect the test type of: {}", unmatched.join("; ")).into());
+    }
+    Ok(groups
+        .into_iter()
+        .map(|(test_type, files)| (test_type.to_string(), files))
+        .collect())
+}
+
+/// Extract the files of one test type into `<test type>.csv`.
+///
+/// Files recorded in the `<test type>.manifest` of an earlier run are only extracted again if
+/// they have changed, unless `--full` is given.
+fn extract_test_type(
+    test_type: &str,
+    cg_files: &[CgFile],
+    options: &Options,
+) -> Result<(), Box<dyn Error>> {
+    let extractor = get_extractor(test_type)?;
+    let output_path = PathBuf::from(test_type).with_extension("csv");
+    let manifest_path = manifest::manifest_path(&output_path);
+    let settings = options.output_settings();
+    let mut manifest = if options.full {
+        Manifest::new(&settings)
+    } else {
+        Manifest::load(&manifest_path, &settings)?
+    };
+
+    let base_header = output_header(&extractor);
+    let mut header = base_header.clone();
+    apply_transforms(&mut header, &mut vec![], &extractor, options);
+    let mut header_line = vec![];
+    write_header(&mut header_line, &header)?;
+    let header_line = String::from_utf8(header_line)?;
+    let file_path_idx = header.iter().position(|h| h == "File Path").unwrap();
+    let test_id_col = if options.long { "Test ID" } else { extractor.test_id };
+    let output_test_id_idx = header.iter().position(|h| h == test_id_col);
+
+    let mut previous_rows = vec![];
+    if !manifest.is_empty() {
+        match manifest::read_previous_rows(&output_path, header_line.trim_end(), file_path_idx)? {
+            Some(rows) => previous_rows = rows,
+            None => {
+                println!(
+                    "{} is missing or has different columns, extracting every file",
+                    output_path.display()
+                );
+                manifest = Manifest::new(&settings);
+            }
+        }
+    }
+
+    let mut to_extract = vec![];
+    for cg_file in cg_files.iter() {
+        if !manifest.is_current(&cg_file.path, extractor.version)? {
+            to_extract.push(cg_file);
+        }
+    }
+    println!(
+        "Files to be processed: {} ({} unchanged since the last run)",
+        to_extract.len(),
+        cg_files.len() - to_extract.len()
+    );
+
+    let test_id_idx = base_header.iter().position(|h| h == extractor.test_id);
+    let mut extracted_files = vec![];
+    let mut reported_warnings = HashSet::new();
+    let reads_dates = excel_tools::has_date_columns(&extractor.sheets, &options.col_type_overrides);
+    for cg_file in to_extract.iter() {
+        let file = &cg_file.path;
         println!("Processing: {}", file.to_str().unwrap());
-        let mut excel: Xlsx<_> = open_workbook(&file)?;
+        let bytes = discovery::read_cg_file(file)?;
+        let date_system = excel_tools::read_date_system(Cursor::new(&bytes)).unwrap_or_else(|| {
+            if reads_dates {
+                println!("Could not tell which date system the workbook uses, assuming 1900 dates");
+            }
+            DateSystem::Excel1900
+        });
+        let hash = util::hash_bytes(&bytes);
+        let mut excel = Workbook::from_bytes(file, bytes)?;
 
-        let validated_extractors = excel_tools::validate_extractors(&mut excel, &extractors);
+        let mut validated_extractors =
+            excel_tools::validate_extractors(&mut excel, &extractor.sheets);
         if validated_extractors.is_none() {
             println!(
                 "File failed sheet validation and will be skipped: {}",
@@ -54,41 +226,388 @@ fn main() -> Result<(), Box<dyn Error>> {
             continue;
         }
 
-        let mut active_rows = vec![];
-        if let Some(Ok(ws)) = excel.worksheet_range("Master List") {
-            active_rows = excel_tools::find_active_rows(&ws, 1, None);
-        }
+        let source_rows =
+            match excel_tools::find_source_rows(&mut excel, &extractor.row_sources, MAX_ROWS) {
+                Some(rows) => rows,
+                None => {
+                    println!(
+                        "File has no sheet to find sample rows in and will be skipped: {}",
+                        file.to_str().unwrap()
+                    );
+                    continue;
+                }
+            };
 
-        let max_row: u32 = 200;
-        let mut col_vecs = vec![];
-        for sheet in validated_extractors.unwrap().iter() {
-            if let Some(Ok(ws)) = excel.worksheet_range(&sheet.sheet_name) {
-                col_vecs.extend(excel_tools::extract_sheet_columns(&ws, &sheet, max_row));
+        let mut rows = vec![vec![]; source_rows.rows.len()];
+        let mut col_names: Vec<&str> = vec![];
+        for sheet in validated_extractors.as_mut().unwrap().iter_mut() {
+            for (col_name, col_type) in options.col_type_overrides.iter() {
+                excel_tools::set_col_type(sheet, col_name, col_type.clone());
             }
+            // a sheet joined by key is matched on a column of the sheets extracted before it
+            let sample_keys: Option<Vec<String>> = sheet
+                .join_key
+                .as_ref()
+                .and_then(|k| col_names.iter().position(|n| *n == k.sample_col))
+                .map(|i| rows.iter().map(|r| excel_tools::key_value(Some(&r[i]))).collect());
+            let sheet_rows = extract_sheet_rows(
+                &mut excel,
+                sheet,
+                &source_rows,
+                sample_keys.as_deref(),
+                date_system,
+                &mut reported_warnings,
+            );
+            rows.iter_mut()
+                .zip(sheet_rows)
+                .for_each(|(row, values)| row.extend(values));
+            col_names.extend(sheet.col_names.iter());
         }
 
-        // Transform columns to rows and push file path to end of each row
-        let file_name_dt = DataType::String(String::from(file.to_str().unwrap()));
-        let mut rows = excel_tools::rows_from_cols(col_vecs, active_rows);
-        rows.iter_mut().for_each(|r| r.push(file_name_dt.clone()));
+        // Transform columns to rows and push the file's metadata to the end of each row
+        let file_metadata = [
+            cg_file
+                .run_date
+                .map_or(DataType::Empty, |d| DataType::String(d.to_string())),
+            cg_file
+                .batch
+                .clone()
+                .map_or(DataType::Empty, DataType::String),
+            DataType::String(String::from(file.to_str().unwrap())),
+        ];
+        rows.iter_mut().for_each(|r| r.extend(file_metadata.iter().cloned()));
 
-        output_data.extend(rows);
+        extracted_files.push((file.as_path(), rows));
+        // only files whose rows were extracted are skipped by later runs
+        manifest.record(file, hash, extractor.version)?;
+    }
+
+    // a batch folder can hold several saves of the same workbook; each sample is only kept
+    // from the newest save holding it, which may have been extracted by an earlier run
+    let extracted: HashSet<&Path> = to_extract.iter().map(|f| f.path.as_path()).collect();
+    previous_rows.retain(|(file, _)| !extracted.contains(file.as_path()));
+    let new_test_ids: Vec<Vec<Option<String>>> = extracted_files
+        .iter()
+        .map(|(_, rows)| {
+            rows.iter()
+                .map(|r| test_id_idx.map(|i| r[i].to_string()))
+                .collect()
+        })
+        .collect();
+    let previous_test_ids: Vec<Option<Cow<str>>> = previous_rows
+        .iter()
+        .map(|(_, line)| output_test_id_idx.and_then(|i| util::csv_field(line, i)))
+        .collect();
+    let mut sample_rows: Vec<(&Path, Option<&str>)> = previous_rows
+        .iter()
+        .zip(previous_test_ids.iter())
+        .map(|((file, _), test_id)| (file.as_path(), test_id.as_deref()))
+        .collect();
+    for ((file, _), test_ids) in extracted_files.iter().zip(new_test_ids.iter()) {
+        sample_rows.extend(test_ids.iter().map(|id| (*file, id.as_deref())));
+    }
+    let duplicates = find_duplicate_rows(&sample_rows, |file| {
+        discovery::cg_file_stamp(file).ok().map(|(_, modified)| modified)
+    });
+    report_duplicates(&sample_rows, &duplicates);
+
+    let is_duplicate: HashSet<usize> = duplicates.iter().map(|(i, _)| *i).collect();
+    let previous_count = previous_rows.len();
+    let previous_lines: Vec<&String> = previous_rows
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| !is_duplicate.contains(i))
+        .map(|(_, (_, line))| line)
+        .collect();
+    let mut output_data: Vec<Vec<DataType>> = extracted_files
+        .into_iter()
+        .flat_map(|(_, rows)| rows)
+        .enumerate()
+        .filter(|(i, _)| !is_duplicate.contains(&(previous_count + i)))
+        .map(|(_, row)| row)
+        .collect();
+
+    let mut header = base_header;
+    apply_transforms(&mut header, &mut output_data, &extractor, options);
+    if options.normalize_units && extractor.units.is_none() {
+        println!("No units declared for {}, skipping normalization", test_type);
+    }
+    if let Some(table) = options.limits.as_ref().filter(|_| extractor.sample_type.is_none()) {
+        let skipped = table.sample_type_limits(&extractor.analytes);
+        if !skipped.is_empty() {
+            println!(
+                "{} has no sample type, so limits for particular sample types are not used for: {}",
+                test_type,
+                skipped.join(", ")
+            );
+        }
     }
 
-    let mut header = excel_tools::make_header(&extractors);
-    header.push("File Path");
-    let output_path = PathBuf::from(test_type).with_extension("csv");
     let mut output_file = BufWriter::new(File::create(output_path).unwrap());
-    write_header(&mut output_file, header)?;
+    write_header(&mut output_file, &header)?;
+    for line in previous_lines.iter() {
+        write!(output_file, "{}\r\n", line)?;
+    }
     write_data(&mut output_file, output_data)?;
-
-    println!("\n\nPress ENTER key to exit ...\n");
-    io::stdin().read_line(&mut String::new())?;
+    output_file.flush()?;
+    manifest.save(&manifest_path)?;
 
     Ok(())
 }
 
-fn write_header<W: Write>(dest: &mut W, header: Vec<&str>) -> std::io::Result<()> {
+/// Report the rows dropped because a newer save in their batch folder holds the same samples.
+fn report_duplicates(sample_rows: &[(&Path, Option<&str>)], duplicates: &[(usize, &Path)]) {
+    let mut dropped: BTreeMap<(&Path, &Path), usize> = BTreeMap::new();
+    for (i, kept) in duplicates.iter() {
+        *dropped.entry((sample_rows[*i].0, *kept)).or_default() += 1;
+    }
+    if dropped.is_empty() {
+        return;
+    }
+    println!("Dropped rows of samples held by a newer save:");
+    for ((file, kept), count) in dropped.iter() {
+        println!("  {}: {} row(s), kept from {}", file.display(), count, kept.display());
+    }
+}
+
+/// Extract one sheet's columns for every sample, matching the sheet's rows to samples by its
+/// join key if it has one, with `sample_keys` holding each sample's key. Samples without a row
+/// in the sheet get empty cells.
+///
+/// Warnings about the template, such as an ambiguous header, are printed the first time they
+/// are seen and then kept in `reported`, so each is reported once per run.
+fn extract_sheet_rows<R: Reader>(
+    excel: &mut R,
+    sheet: &Sheet,
+    source_rows: &SourceRows,
+    sample_keys: Option<&[String]>,
+    date_system: DateSystem,
+    reported: &mut HashSet<String>,
+) -> Vec<Vec<DataType>> {
+    let width = sheet.col_names.len();
+    let ws = match excel.worksheet_range(sheet.sheet_name) {
+        Some(Ok(ws)) => ws,
+        _ => return vec![vec![DataType::Empty; width]; source_rows.rows.len()],
+    };
+    let layout = sheet.layout.locate(&ws);
+    let mut report_once = |warning: String| {
+        if !reported.contains(&warning) {
+            println!("{}", warning);
+            reported.insert(warning);
+        }
+    };
+
+    let by_position = || source_rows.rows.iter().map(|r| Some(*r)).collect();
+    let sheet_row_nums = match (&sheet.join_key, sample_keys) {
+        (Some(join_key), Some(keys)) => match excel_tools::join_rows(
+            &ws,
+            &layout,
+            &join_key.sheet_col,
+            keys,
+            MAX_ROWS,
+        ) {
+            Some(joined) => {
+                let missing: Vec<&str> = keys
+                    .iter()
+                    .zip(joined.rows.iter())
+                    .filter(|(key, row)| row.is_none() && !key.is_empty())
+                    .map(|(key, _)| key.as_str())
+                    .collect();
+                if !missing.is_empty() {
+                    println!(
+                        "Sheet '{}' has no row for: {}",
+                        sheet.sheet_name,
+                        missing.join(", ")
+                    );
+                }
+                if !joined.unmatched.is_empty() {
+                    println!(
+                        "Sheet '{}' has rows matching no sample: {}",
+                        sheet.sheet_name,
+                        joined.unmatched.join(", ")
+                    );
+                }
+                joined.rows
+            }
+            None => {
+                report_once(format!(
+                    "Sheet '{}' has no join key column {:?}, its rows are matched by position",
+                    sheet.sheet_name, join_key.sheet_col
+                ));
+                by_position()
+            }
+        },
+        _ => by_position(),
+    };
+
+    for warning in excel_tools::ambiguous_headers(&ws, sheet, &layout) {
+        report_once(warning);
+    }
+    let mut cols = excel_tools::extract_sheet_columns(&ws, sheet, &layout, MAX_ROWS);
+    excel_tools::apply_col_types(&mut cols, sheet, date_system);
+    excel_tools::rows_from_cols(&cols, &sheet_row_nums, width)
+}
+
+/// Names of the output columns before any transform, ending with the file's metadata.
+fn output_header(extractor: &Extractor) -> Vec<String> {
+    let mut header: Vec<String> = excel_tools::make_header(&extractor.sheets)
+        .iter()
+        .map(|h| h.to_string())
+        .collect();
+    header.extend(
+        ["Run Date", "Batch", "File Path"]
+            .iter()
+            .map(|h| h.to_string()),
+    );
+    header
+}
+
+/// Apply the post-extraction transforms, which add columns to `header` and every row.
+fn apply_transforms(
+    header: &mut Vec<String>,
+    rows: &mut Vec<Vec<DataType>>,
+    extractor: &Extractor,
+    options: &Options,
+) {
+    transforms::split_qualified_results(header, rows, &extractor.qualified_results);
+    transforms::check_cfu(header, rows, &extractor.cfu_checks, options.cfu_tolerance);
+    transforms::attach_limits(header, rows, &extractor.analytes);
+    if options.normalize_units {
+        if let Some(spec) = &extractor.units {
+            let columns: Vec<String> = extractor
+                .analytes
+                .iter()
+                .flat_map(|a| a.value_columns())
+                .collect();
+            transforms::normalize_units(header, rows, &columns, spec);
+        }
+    }
+    if let Some(table) = &options.limits {
+        let columns = transforms::ComplianceColumns {
+            analytes: &extractor.analytes,
+            sample_type: extractor.sample_type,
+            units: extractor.units.as_ref(),
+        };
+        transforms::evaluate_compliance(header, rows, table, &columns);
+    }
+    if options.long {
+        let columns = transforms::LongColumns {
+            test_id: extractor.test_id,
+            sample_name: extractor.sample_name,
+            sample_type: extractor.sample_type,
+            analytes: &extractor.analytes,
+            units: extractor.units.as_ref(),
+        };
+        transforms::unpivot(header, rows, &columns);
+    }
+}
+
+/// Poll the root directory and extract new and changed files once they are fully written.
+///
+/// A file is ready when its size and modification time are the same on two consecutive polls
+/// and Excel has no lock file open beside it. Rows are added to the test type's output as with
+/// an incremental run. A `--last` range moves forward with the date.
+///
+/// Usage: `watch <root dir> <company> <test name> [--interval <seconds>] [options]`
+fn watch(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let mut options = options::parse_options(args)?;
+    if !options.files.is_empty() {
+        return Err("watch searches a root directory and cannot be given listed files".into());
+    }
+    // every poll writes only the files that became ready, so it must add to earlier output
+    if options.full {
+        return Err("watch only extracts new and changed files and cannot be given --full".into());
+    }
+    println!(
+        "Watching for new files every {} seconds, press Ctrl-C to stop",
+        options.poll_interval.as_secs()
+    );
+
+    let settings = options.output_settings();
+    let mut last_seen = HashMap::new();
+    loop {
+        if let Some(days) = options.last_days {
+            options.date_range = DateRange::last(days, Date::today());
+        }
+        for (test_type, cg_files) in discover_files(&options)? {
+            let (cg_files, _) = options.exclusions.apply(cg_files);
+            let extractor = get_extractor(&test_type)?;
+            let output_path = PathBuf::from(&test_type).with_extension("csv");
+            let manifest = Manifest::load(&manifest::manifest_path(&output_path), &settings)?;
+
+            let mut ready = vec![];
+            for cg_file in cg_files.iter() {
+                let stamp = match discovery::cg_file_stamp(&cg_file.path) {
+                    Ok(stamp) => stamp,
+                    Err(_) => continue,
+                };
+                if manifest.is_current(&cg_file.path, extractor.version)? {
+                    continue;
+                }
+                let stable = last_seen.insert(cg_file.path.clone(), stamp) == Some(stamp);
+                if stable && !has_lock_file(&cg_file.path) {
+                    ready.push(cg_file.clone());
+                }
+            }
+            if !ready.is_empty() {
+                // a file that cannot be read yet is tried again on the next poll
+                if let Err(e) = extract_test_type(&test_type, &ready, &options) {
+                    println!("Extraction failed, retrying on the next poll: {}", e);
+                }
+            }
+        }
+        // let archives be replaced between polls, and read afresh on the next one
+        discovery::close_archives();
+        thread::sleep(options.poll_interval);
+    }
+}
+
+/// Check the extractor definition for a test type, optionally against a sample workbook.
+///
+/// Usage: `check-config <company> <test name> [sample workbook]`
+fn check_config(args: &[String]) -> Result<(), Box<dyn Error>> {
+    if args.len() < 2 {
+        return Err("Usage: check-config <company> <test name> [sample workbook]".into());
+    }
+    let test_type = [args[0].as_str(), args[1].as_str()].join("_");
+    println!("Checking configuration for: {}", test_type);
+
+    let mut problems = vec![];
+    match get_regex(&test_type) {
+        Some(Ok(_)) => (),
+        Some(Err(e)) => problems.push(format!("Invalid file discovery regex: {}", e)),
+        None => problems.push(format!("No file discovery regex for {}", test_type)),
+    }
+    let extractor = get_extractor(&test_type)?;
+    problems.extend(excel_tools::check_structure(&extractor.sheets));
+    problems.extend(excel_tools::check_row_sources(&extractor.row_sources));
+
+    // a definition with problems, such as an invalid header pattern, cannot be run
+    let definition_ok = problems.is_empty();
+    if let Some(sample) = args.get(2).filter(|_| definition_ok) {
+        println!("Checking against sample workbook: {}", sample);
+        let sample = Path::new(sample);
+        let mut excel = Workbook::from_bytes(sample, discovery::read_cg_file(sample)?)?;
+        problems.extend(excel_tools::check_workbook(&mut excel, &extractor.sheets));
+        problems.extend(excel_tools::check_workbook_rows(&mut excel, &extractor.row_sources));
+        excel_tools::report_aliases(&mut excel, &extractor.sheets)
+            .iter()
+            .for_each(|line| println!("  {}", line));
+    }
+
+    if !definition_ok && args.len() > 2 {
+        println!("The sample workbook is not checked until the definition's problems are fixed");
+    }
+    if problems.is_empty() {
+        println!("No problems found");
+        Ok(())
+    } else {
+        problems.iter().for_each(|p| println!("  {}", p));
+        Err(format!("{} problem(s) found in {} configuration", problems.len(), test_type).into())
+    }
+}
+
+fn write_header<W: Write>(dest: &mut W, header: &[String]) -> std::io::Result<()> {
     let len_header = header.len() - 1;
     for (i, h) in header.iter().enumerate() {
         write!(dest, r#""{}""#, h)?;
@@ -121,119 +640,3 @@ fn write_data<W: Write>(dest: &mut W, data: Vec<Vec<DataType>>) -> std::io::Resu
     }
     Ok(())
 }
-
-fn find_cg_files(
-    root: &PathBuf,
-    regex_struct: &TestTypeRegex,
-    year_dir_regex: String,
-    month_dir_regex: String,
-) -> Vec<PathBuf> {
-    let year_regex = Regex::new(year_dir_regex.as_str()).unwrap();
-    let months_regex = Regex::new(month_dir_regex.as_str()).unwrap();
-    let days_regex = RegexBuilder::new(DAY_DIR_REGEX)
-        .case_insensitive(true)
-        .build()
-        .unwrap();
-
-    match_child_paths(&root, &year_regex)
-        .iter()
-        .filter(|p| p.is_dir())
-        .flat_map(|p| match_child_paths(&p, &months_regex))
-        .filter(|p| p.is_dir())
-        .flat_map(|p| match_child_paths(&p, &days_regex))
-        .filter(|p| p.is_dir())
-        .flat_map(|p| match_child_paths(&p, &regex_struct.folder))
-        .filter(|p| p.is_dir())
-        .flat_map(|p| match_child_paths(&p, &regex_struct.file))
-        .collect()
-}
-
-fn parse_range_arg(range_str: String) -> Result<String, Box<dyn Error>> {
-    let start_stop: Vec<&str> = range_str.split('-').collect();
-    let start: u32 = start_stop[0].parse()?;
-    let mut end = start;
-    if start_stop.len() == 2 {
-        end = start_stop[1].parse()?;
-    }
-    Ok((start..=end)
-        .map(|i| i.to_string())
-        .collect::<Vec<String>>()
-        .join("|"))
-}
-
-fn match_child_paths(parent_dir: &PathBuf, child_regex: &Regex) -> Vec<PathBuf> {
-    fs::read_dir(parent_dir)
-        .unwrap()
-        .filter_map(|r| filter_by_filename(r, child_regex))
-        .map(|e| e.path())
-        .collect()
-}
-
-fn filter_by_filename(
-    dir: Result<fs::DirEntry, io::Error>,
-    pattern: &Regex,
-) -> Option<fs::DirEntry> {
-    match dir {
-        Ok(entry) => {
-            if pattern.is_match(entry.path().file_name().unwrap().to_str().unwrap()) {
-                Some(entry)
-            } else {
-                None
-            }
-        }
-        Err(_) => None,
-    }
-}
-
-struct TestTypeRegex {
-    pub folder: Regex,
-    pub file: Regex,
-}
-
-impl TestTypeRegex {
-    fn new(folder_regex: &str, file_regex: &str) -> Self {
-        TestTypeRegex {
-            folder: RegexBuilder::new(folder_regex)
-                .case_insensitive(true)
-                .build()
-                .unwrap(),
-            file: RegexBuilder::new(file_regex)
-                .case_insensitive(true)
-                .build()
-                .unwrap(),
-        }
-    }
-}
-
-fn get_regex(test_type: &str) -> Option<TestTypeRegex> {
-    match test_type {
-        // Botanacor files
-        "botanacor_potency" => Some(TestTypeRegex::new(
-            r"^botanacor potency ",
-            r"^cert generator botanacor potency .*\.xlsm$",
-        )),
-        "botanacor_pesticides" => Some(TestTypeRegex::new(
-            r"^botanacor pesticides ",
-            r"^cert generator botanacor pesticides .*\.xlsm$",
-        )),
-        "botanacor_metals" => Some(TestTypeRegex::new(
-            r"^botanacor metals ",
-            r"^cert generator botanacor metals .*\.xlsm$",
-        )),
-        "botanacor_micro" => Some(TestTypeRegex::new(
-            "^validated botanacor micro ",
-            r"^cert generator botanacor micro .*\.xlsm$",
-        )),
-
-        // Agricor files
-        "agricor_micro" => Some(TestTypeRegex::new(
-            "^agricor micro ",
-            r"^cert generator agricor micro .*\.xlsm$",
-        )),
-        "agricor_potency" => Some(TestTypeRegex::new(
-            "^agricor potency ",
-            r"^cert generator agricor potency .*\.xlsm$",
-        )),
-        _ => None,
-    }
-}
@@ -0,0 +1,249 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::discovery::{cg_file_stamp, read_cg_file};
+use crate::util::{csv_field, hash_bytes};
+
+const SETTINGS_PREFIX: &str = "# settings: ";
+
+/// What a file looked like when it was extracted.
+struct Entry {
+    size: u64,
+    /// Seconds since the Unix epoch.
+    modified: u64,
+    hash: u64,
+    extractor_version: u32,
+}
+
+/// The files already extracted into an output CSV, stored next to it so later runs only
+/// extract new and changed files.
+///
+/// Each line holds a file's path, size, modification time, content hash and the version of
+/// the extractor that read it, separated by tabs.
+pub struct Manifest {
+    /// Output settings the recorded files were extracted with.
+    settings: String,
+    entries: BTreeMap<PathBuf, Entry>,
+}
+
+impl Manifest {
+    pub fn new(settings: &str) -> Self {
+        Manifest {
+            settings: settings.to_string(),
+            entries: BTreeMap::new(),
+        }
+    }
+
+    /// Load the manifest at `path`, or start an empty one if there is none or it was written
+    /// with different output settings.
+    pub fn load(path: &Path, settings: &str) -> io::Result<Self> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Manifest::new(settings)),
+            Err(e) => return Err(e),
+        };
+        let mut lines = BufReader::new(file).lines();
+        match lines.next().transpose()? {
+            Some(line) if line.strip_prefix(SETTINGS_PREFIX) == Some(settings) => (),
+            _ => return Ok(Manifest::new(settings)),
+        }
+
+        let mut manifest = Manifest::new(settings);
+        for line in lines {
+            if let Some((path, entry)) = parse_entry(&line?) {
+                manifest.entries.insert(path, entry);
+            }
+        }
+        Ok(manifest)
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut dest = BufWriter::new(File::create(path)?);
+        writeln!(dest, "{}{}", SETTINGS_PREFIX, self.settings)?;
+        for (path, entry) in self.entries.iter() {
+            writeln!(
+                dest,
+                "{}\t{}\t{}\t{:016x}\t{}",
+                path.display(),
+                entry.size,
+                entry.modified,
+                entry.hash,
+                entry.extractor_version
+            )?;
+        }
+        dest.flush()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Whether `path` was extracted by this extractor version and has not changed since.
+    ///
+    /// Files whose size and modification time still match are not read again; a file that was
+    /// only touched is recognised by its content hash.
+    pub fn is_current(&self, path: &Path, extractor_version: u32) -> io::Result<bool> {
+        let entry = match self.entries.get(path) {
+            Some(entry) if entry.extractor_version == extractor_version => entry,
+            _ => return Ok(false),
+        };
+        let (size, modified) = cg_file_stamp(path)?;
+        if size != entry.size {
+            return Ok(false);
+        }
+        Ok(modified == entry.modified || hash_file(path)? == entry.hash)
+    }
+
+    /// Record that `path`, whose content hashes to `hash`, has been extracted in its current
+    /// state.
+    pub fn record(&mut self, path: &Path, hash: u64, extractor_version: u32) -> io::Result<()> {
+        let (size, modified) = cg_file_stamp(path)?;
+        let entry = Entry {
+            size,
+            modified,
+            hash,
+            extractor_version,
+        };
+        self.entries.insert(path.to_path_buf(), entry);
+        Ok(())
+    }
+}
+
+fn parse_entry(line: &str) -> Option<(PathBuf, Entry)> {
+    let fields: Vec<&str> = line.split('\t').collect();
+    match fields[..] {
+        [path, size, modified, hash, version] => Some((
+            PathBuf::from(path),
+            Entry {
+                size: size.parse().ok()?,
+                modified: modified.parse().ok()?,
+                hash: u64::from_str_radix(hash, 16).ok()?,
+                extractor_version: version.parse().ok()?,
+            },
+        )),
+        _ => None,
+    }
+}
+
+/// Where the manifest of the output CSV at `output_path` is kept.
+pub fn manifest_path(output_path: &Path) -> PathBuf {
+    output_path.with_extension("manifest")
+}
+
+/// 64 bit FNV-1a hash of a file's contents.
+fn hash_file(path: &Path) -> io::Result<u64> {
+    Ok(hash_bytes(&read_cg_file(path)?))
+}
+
+/// Read the rows of an earlier output CSV, paired with the file each came from.
+///
+/// Returns `None` when the output is missing or its header is not `header_line`, in which case
+/// none of its rows can be reused.
+pub fn read_previous_rows(
+    path: &Path,
+    header_line: &str,
+    file_path_idx: usize,
+) -> io::Result<Option<Vec<(PathBuf, String)>>> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let mut lines = contents.split("\r\n");
+    if lines.next() != Some(header_line) {
+        return Ok(None);
+    }
+    Ok(Some(
+        lines
+            .filter(|l| !l.is_empty())
+            .map(|l| {
+                let file = csv_field(l, file_path_idx).unwrap_or_default();
+                (PathBuf::from(&*file), l.to_string())
+            })
+            .collect(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::temp_dir;
+
+    /// Set the modification time of `path` to `secs` after the Unix epoch.
+    fn set_modified(path: &Path, secs: u64) {
+        let time = std::time::UNIX_EPOCH + std::time::Duration::from_secs(secs);
+        File::options().write(true).open(path).unwrap().set_modified(time).unwrap();
+    }
+
+    #[test]
+    fn round_trip() {
+        let dir = temp_dir("manifest_round_trip");
+        let workbook = dir.join("cert generator agricor micro A.xlsm");
+        fs::write(&workbook, b"v1").unwrap();
+        let manifest_path = manifest_path(&dir.join("agricor_micro.csv"));
+        assert_eq!(manifest_path, dir.join("agricor_micro.manifest"));
+
+        let mut manifest = Manifest::load(&manifest_path, "long=false").unwrap();
+        assert!(manifest.is_empty());
+        manifest.record(&workbook, hash_bytes(b"v1"), 3).unwrap();
+        manifest.save(&manifest_path).unwrap();
+
+        let loaded = Manifest::load(&manifest_path, "long=false").unwrap();
+        assert!(loaded.is_current(&workbook, 3).unwrap());
+        let entry = &loaded.entries[&workbook];
+        assert_eq!((entry.size, entry.hash), (2, hash_bytes(b"v1")));
+        // another extractor version or other settings extract the file again
+        assert!(!loaded.is_current(&workbook, 4).unwrap());
+        assert!(Manifest::load(&manifest_path, "long=true").unwrap().is_empty());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn changes_are_found_by_stamp_and_hash() {
+        let dir = temp_dir("manifest_changes");
+        let workbook = dir.join("cert generator agricor micro A.xlsm");
+        fs::write(&workbook, b"v1").unwrap();
+        set_modified(&workbook, 1_000_000);
+        let mut manifest = Manifest::new("");
+        manifest.record(&workbook, hash_bytes(b"v1"), 1).unwrap();
+
+        // touched without being changed
+        set_modified(&workbook, 2_000_000);
+        assert!(manifest.is_current(&workbook, 1).unwrap());
+        // changed without changing size
+        fs::write(&workbook, b"v2").unwrap();
+        set_modified(&workbook, 3_000_000);
+        assert!(!manifest.is_current(&workbook, 1).unwrap());
+        fs::write(&workbook, b"v10").unwrap();
+        assert!(!manifest.is_current(&workbook, 1).unwrap());
+        assert!(!manifest.is_current(&dir.join("other.xlsm"), 1).unwrap());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn unreadable_entries_are_skipped() {
+        assert!(parse_entry("a.xlsm\t2\t100\t00000000000000ff\t3").is_some());
+        assert!(parse_entry("a.xlsm\t2\t100\tnot hex\t3").is_none());
+        assert!(parse_entry("a.xlsm\t2\t100").is_none());
+    }
+
+    #[test]
+    fn previous_rows() {
+        let dir = temp_dir("manifest_previous");
+        let output = dir.join("agricor_micro.csv");
+        let header = "\"Test ID\",\"File Path\"";
+        let missing = read_previous_rows(&output, header, 1).unwrap();
+        assert!(missing.is_none());
+        fs::write(&output, format!("{}\r\n\"T-1\",\"a, b.xlsm\"\r\n\"T-2\",\"c.xlsm\"\r\n", header))
+            .unwrap();
+        let rows = read_previous_rows(&output, header, 1).unwrap().unwrap();
+        assert_eq!(rows[0], (PathBuf::from("a, b.xlsm"), "\"T-1\",\"a, b.xlsm\"".to_string()));
+        assert_eq!(rows[1].0, PathBuf::from("c.xlsm"));
+        assert!(read_previous_rows(&output, "\"Test ID\"", 1).unwrap().is_none());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,297 @@
+use std::error::Error;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::dates::{parse_day_count, Date, DateRange};
+use crate::discovery::{
+    read_file_list, Exclusions, Layout, MonthDirStyle, DEFAULT_EXCLUDES, DEFAULT_LAYOUT,
+};
+use crate::excel_tools::ColType;
+use crate::transforms::LimitsTable;
+
+pub struct Options {
+    /// Arguments that are not options, in order.
+    pub positional: Vec<String>,
+    pub normalize_units: bool,
+    /// Write one row per sample and analyte instead of one row per sample.
+    pub long: bool,
+    /// Action limits to evaluate results against.
+    pub limits: Option<LimitsTable>,
+    /// Largest difference, as a fraction, allowed between a reported CFU/g and the one
+    /// recomputed from its plate count.
+    pub cfu_tolerance: f64,
+    pub col_type_overrides: Vec<(String, ColType)>,
+    /// Run dates of the day directories to search.
+    pub date_range: DateRange,
+    /// Number of days given with `--last`, counted back from the day `date_range` is resolved.
+    pub last_days: Option<i64>,
+    pub month_dir_style: MonthDirStyle,
+    /// Where cert generator files sit below the root directory.
+    pub layout: Layout,
+    /// Workbooks to extract instead of searching a root directory.
+    pub files: Vec<PathBuf>,
+    /// Extract every file again instead of only new and changed ones.
+    pub full: bool,
+    /// How long `watch` waits between looks at the root directory.
+    pub poll_interval: Duration,
+    /// File names left out of the run.
+    pub exclusions: Exclusions,
+}
+
+/// Split command line arguments into options, which start with "--", and positional arguments.
+pub fn parse_options(args: &[String]) -> Result<Options, Box<dyn Error>> {
+    let mut options = Options {
+        positional: vec![],
+        normalize_units: false,
+        long: false,
+        limits: None,
+        cfu_tolerance: 0.1,
+        col_type_overrides: vec![],
+        date_range: DateRange::default(),
+        last_days: None,
+        month_dir_style: MonthDirStyle::Any,
+        layout: DEFAULT_LAYOUT.parse()?,
+        files: vec![],
+        full: false,
+        poll_interval: Duration::from_secs(30),
+        exclusions: Exclusions::default(),
+    };
+    let mut limits_path = None;
+    let mut jurisdiction = None;
+    let mut exclude_patterns = vec![];
+    let mut default_excludes = true;
+    let mut from_to_given = false;
+    let mut ranges_given = 0;
+
+    let mut arg_iter = args.iter().peekable();
+    while let Some(arg) = arg_iter.next() {
+        match arg.as_str() {
+            "--normalize-units" => options.normalize_units = true,
+            "--full" => options.full = true,
+            "--long" => options.long = true,
+            "--col-type" => options
+                .col_type_overrides
+                .push(parse_col_type_arg(option_value(arg, arg_iter.next())?)?),
+            "--from" | "--to" => {
+                let value = option_value(arg, arg_iter.next())?;
+                let date = Date::parse_iso(value)
+                    .ok_or_else(|| format!("Could not parse {} date '{}'", arg, value))?;
+                if arg == "--from" {
+                    options.date_range.from = Some(date);
+                } else {
+                    options.date_range.to = Some(date);
+                }
+                if !from_to_given {
+                    from_to_given = true;
+                    ranges_given += 1;
+                }
+            }
+            "--last" => {
+                let value = option_value(arg, arg_iter.next())?;
+                let days = parse_day_count(value)
+                    .ok_or_else(|| format!("Could not parse --last '{}', expected e.g. 30d", value))?;
+                options.last_days = Some(days);
+                options.date_range = DateRange::last(days, Date::today());
+                ranges_given += 1;
+            }
+            "--month" => {
+                let value = option_value(arg, arg_iter.next())?;
+                options.date_range = DateRange::parse_month(value).ok_or_else(|| {
+                    format!("Could not parse --month '{}', expected e.g. 2020-03", value)
+                })?;
+                ranges_given += 1;
+            }
+            "--files" => {
+                while let Some(file) = arg_iter.next_if(|a| !a.starts_with("--")) {
+                    options.files.push(PathBuf::from(file));
+                }
+            }
+            "--files-from" => {
+                let source = option_value(arg, arg_iter.next())?;
+                options.files.extend(
+                    read_file_list(source)
+                        .map_err(|e| format!("Could not read file list {}: {}", source, e))?,
+                );
+            }
+            "--interval" => {
+                let value = option_value(arg, arg_iter.next())?;
+                let secs: u64 = value
+                    .parse()
+                    .ok()
+                    .filter(|s| *s > 0)
+                    .ok_or_else(|| format!("Could not parse --interval '{}' as seconds", value))?;
+                options.poll_interval = Duration::from_secs(secs);
+            }
+            "--limits" => {
+                limits_path = Some(PathBuf::from(option_value(arg, arg_iter.next())?))
+            }
+            "--cfu-tolerance" => {
+                let value = option_value(arg, arg_iter.next())?;
+                let percent: f64 = value
+                    .parse()
+                    .ok()
+                    .filter(|p: &f64| *p >= 0.0)
+                    .ok_or_else(|| {
+                        format!("Could not parse --cfu-tolerance '{}' as a percentage", value)
+                    })?;
+                options.cfu_tolerance = percent / 100.0;
+            }
+            "--jurisdiction" => jurisdiction = Some(option_value(arg, arg_iter.next())?),
+            "--exclude" => exclude_patterns.push(option_value(arg, arg_iter.next())?.to_string()),
+            "--no-default-excludes" => default_excludes = false,
+            "--layout" => options.layout = option_value(arg, arg_iter.next())?.parse()?,
+            "--month-dirs" => {
+                options.month_dir_style = option_value(arg, arg_iter.next())?.parse()?
+            }
+            _ if arg.starts_with("--") => return Err(format!("Unknown option {}", arg).into()),
+            _ => options.positional.push(arg.to_string()),
+        }
+    }
+
+    if ranges_given > 1 {
+        return Err("Use only one of --from/--to, --last or --month".into());
+    }
+    if let DateRange {
+        from: Some(from),
+        to: Some(to),
+    } = options.date_range
+    {
+        if from > to {
+            return Err(format!("--from {} is later than --to {}", from, to).into());
+        }
+    }
+    if !options.files.is_empty() && !options.positional.is_empty() {
+        return Err("Listed files cannot be combined with a root directory search".into());
+    }
+    if ranges_given > 0 && !options.files.is_empty() {
+        return Err("Listed files are all extracted and cannot be given a date range".into());
+    }
+    if ranges_given > 0 && !options.layout.has_day() {
+        return Err("--from/--to, --last and --month need a layout with a {day:...} directory, \
+                    which the given --layout does not have"
+            .into());
+    }
+    options.limits = match (limits_path, jurisdiction) {
+        (Some(path), Some(jurisdiction)) => Some(LimitsTable::load(&path, jurisdiction)?),
+        (None, None) => None,
+        _ => return Err("--limits and --jurisdiction must be given together".into()),
+    };
+    if default_excludes {
+        exclude_patterns.extend(DEFAULT_EXCLUDES.iter().map(|p| p.to_string()));
+    }
+    options.exclusions = Exclusions::new(&exclude_patterns)?;
+    Ok(options)
+}
+
+impl Options {
+    /// The options that change what is written for a file, so output written with different
+    /// settings is not mixed with new output.
+    pub fn output_settings(&self) -> String {
+        let col_types: Vec<String> = self
+            .col_type_overrides
+            .iter()
+            .map(|(name, col_type)| format!("{}={:?}", name, col_type))
+            .collect();
+        let limits = self.limits.as_ref().map_or(String::new(), |table| {
+            format!("{}:{:016x}", table.jurisdiction, table.fingerprint)
+        });
+        format!(
+            "normalize_units={} long={} limits={} cfu_tolerance={} col_types=[{}] layout={} \
+             month_dirs={:?}",
+            self.normalize_units,
+            self.long,
+            limits,
+            self.cfu_tolerance,
+            col_types.join(","),
+            self.layout,
+            self.month_dir_style
+        )
+    }
+}
+
+fn option_value<'a>(option: &str, value: Option<&'a String>) -> Result<&'a str, String> {
+    value
+        .map(|v| v.as_str())
+        .ok_or_else(|| format!("{} requires a value", option))
+}
+
+/// Parse a `--col-type` value of the form `<column name>=<type>`.
+fn parse_col_type_arg(arg: &str) -> Result<(String, ColType), Box<dyn Error>> {
+    let (col_name, col_type) = arg
+        .rsplit_once('=')
+        .ok_or_else(|| format!("Could not parse column type '{}'", arg))?;
+    Ok((col_name.to_string(), col_type.parse()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options, Box<dyn Error>> {
+        let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+        parse_options(&args)
+    }
+
+    #[test]
+    fn output_settings_follow_the_layout_and_month_dirs() {
+        let settings = |args: &[&str]| parse(args).unwrap().output_settings();
+        let default = settings(&[]);
+        assert!(default.contains(&format!("layout={} month_dirs=Any", DEFAULT_LAYOUT)));
+        assert_ne!(settings(&["--layout", "**/{file}"]), default);
+        assert_ne!(settings(&["--month-dirs", "name"]), default);
+        assert_eq!(settings(&["root", "agricor", "micro"]), default);
+    }
+
+    fn error(args: &[&str]) -> String {
+        match parse(args) {
+            Ok(_) => panic!("{:?} should be rejected", args),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[test]
+    fn listed_files_and_positional_arguments() {
+        // the file list ends at the next option
+        let options = parse(&["--files", "a.xlsm", "b.xlsm", "--long"]).unwrap();
+        assert!(options.positional.is_empty());
+        assert_eq!(options.files, [PathBuf::from("a.xlsm"), PathBuf::from("b.xlsm")]);
+        assert!(options.long);
+
+        let options = parse(&["root", "--long", "agricor", "micro"]).unwrap();
+        assert_eq!(options.positional, ["root", "agricor", "micro"]);
+        assert!(options.files.is_empty());
+
+        assert_eq!(
+            error(&["root", "--files", "a.xlsm"]),
+            "Listed files cannot be combined with a root directory search"
+        );
+    }
+
+    #[test]
+    fn listed_files_have_no_date_range() {
+        for range in [&["--month", "2020-03"][..], &["--last", "30d"], &["--from", "2020-03-01"]] {
+            let args: Vec<&str> = ["--files", "a.xlsm"].iter().chain(range).copied().collect();
+            assert_eq!(
+                error(&args),
+                "Listed files are all extracted and cannot be given a date range"
+            );
+        }
+    }
+
+    #[test]
+    fn limits_need_a_jurisdiction() {
+        let message = "--limits and --jurisdiction must be given together";
+        assert_eq!(error(&["--limits", "limits.csv"]), message);
+        assert_eq!(error(&["--jurisdiction", "CO"]), message);
+    }
+
+    #[test]
+    fn from_must_not_be_after_to() {
+        assert_eq!(
+            error(&["--from", "2020-03-02", "--to", "2020-03-01"]),
+            "--from 2020-03-02 is later than --to 2020-03-01"
+        );
+        let options = parse(&["--to", "2020-03-01", "--from", "2020-03-01"]).unwrap();
+        assert_eq!(options.date_range.from, options.date_range.to);
+    }
+}
@@ -0,0 +1,206 @@
+use calamine::DataType;
+
+use super::numeric_value;
+
+/// The output columns a reported CFU/g result is recomputed from.
+pub struct CfuCheck {
+    pub colony_count: &'static str,
+    /// Dilution factor of the counted plate, e.g. 10 or 100.
+    pub dilution_plate: &'static str,
+    pub sample_weight: &'static str,
+    pub diluent_volume: &'static str,
+    /// Column holding the CFU/g reported on the certificate.
+    pub reported: &'static str,
+}
+
+/// Recompute CFU/g from a row's colony count, plate dilution, sample weight and diluent volume.
+///
+/// This is the cert generator's calculation. The sample is suspended in the diluent, which
+/// dilutes it by `(weight + volume) / weight`, and the suspension is diluted `dilution_plate`
+/// times more before it is plated, so
+/// `CFU/g = count * dilution_plate * (weight + volume) / weight`.
+/// For example, an Agricor TYM plate of 12 colonies at 1:10, from 1 g of sample in 9 mL,
+/// reports 12 * 10 * (1 + 9) / 1 = 1200 CFU/g.
+fn recompute_cfu(count: f64, dilution_plate: f64, weight: f64, volume: f64) -> Option<f64> {
+    if weight <= 0.0 {
+        return None;
+    }
+    Some(count * dilution_plate * (weight + volume) / weight)
+}
+
+/// Recompute each checked CFU/g result and compare it with the reported one, adding
+/// "<reported> Recomputed" and "<reported> QC" columns after the reported result and its
+/// qualifier.
+///
+/// QC is "Mismatch" when the reported value differs from the recomputed one by more than
+/// `tolerance`, a fraction of the recomputed value, and "Ok" otherwise. Rows missing an input,
+/// and qualified results such as "TNTC" or "< 10", are not checked.
+pub fn check_cfu(
+    header: &mut Vec<String>,
+    rows: &mut [Vec<DataType>],
+    checks: &[CfuCheck],
+    tolerance: f64,
+) {
+    for check in checks.iter() {
+        let position = |name: &str| header.iter().position(|h| h == name);
+        let reported_idx = match position(check.reported) {
+            Some(idx) => idx,
+            None => continue,
+        };
+        let qualifier_idx = position(&format!("{} Qualifier", check.reported));
+        let input_idxs = [
+            position(check.colony_count),
+            position(check.dilution_plate),
+            position(check.sample_weight),
+            position(check.diluent_volume),
+        ];
+        let insert_idx = qualifier_idx.unwrap_or(reported_idx) + 1;
+
+        for row in rows.iter_mut() {
+            let is_qualified = qualifier_idx.is_some_and(|i| !row[i].is_empty());
+            let inputs: Option<Vec<f64>> = input_idxs
+                .iter()
+                .map(|idx| idx.and_then(|i| numeric_value(&row[i])))
+                .collect();
+            let recomputed = match inputs {
+                Some(inputs) if !is_qualified => {
+                    recompute_cfu(inputs[0], inputs[1], inputs[2], inputs[3])
+                }
+                _ => None,
+            };
+            let qc = match (recomputed, numeric_value(&row[reported_idx])) {
+                (Some(recomputed), Some(reported)) => {
+                    let diverges = if recomputed == 0.0 {
+                        reported != 0.0
+                    } else {
+                        ((reported - recomputed) / recomputed).abs() > tolerance
+                    };
+                    let qc = if diverges { "Mismatch" } else { "Ok" };
+                    DataType::String(qc.to_string())
+                }
+                _ => DataType::Empty,
+            };
+            row.splice(
+                insert_idx..insert_idx,
+                vec![recomputed.map_or(DataType::Empty, DataType::Float), qc],
+            );
+        }
+        header.splice(
+            insert_idx..insert_idx,
+            vec![
+                format!("{} Recomputed", check.reported),
+                format!("{} QC", check.reported),
+            ],
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::extractors::get_extractor;
+
+    fn check(reported: f64, recomputed: f64, tolerance: f64) -> DataType {
+        let mut header: Vec<String> = ["Count", "Plate", "Weight", "Volume", "Reported"]
+            .iter()
+            .map(|h| h.to_string())
+            .collect();
+        // recomputed from 1 g in 9 mL at 1:1, so the count is a tenth of the result
+        let mut rows = vec![vec![
+            DataType::Float(recomputed / 10.0),
+            DataType::Int(1),
+            DataType::Int(1),
+            DataType::Int(9),
+            DataType::Float(reported),
+        ]];
+        let checks = [CfuCheck {
+            colony_count: "Count",
+            dilution_plate: "Plate",
+            sample_weight: "Weight",
+            diluent_volume: "Volume",
+            reported: "Reported",
+        }];
+        check_cfu(&mut header, &mut rows, &checks, tolerance);
+        assert_eq!(header[5..], ["Reported Recomputed", "Reported QC"]);
+        rows[0][6].clone()
+    }
+
+    fn qc(s: &str) -> DataType {
+        DataType::String(s.to_string())
+    }
+
+    #[test]
+    fn formula() {
+        assert_eq!(recompute_cfu(12.0, 10.0, 1.0, 9.0), Some(1200.0));
+        assert_eq!(recompute_cfu(25.0, 100.0, 10.0, 90.0), Some(25000.0));
+        assert_eq!(recompute_cfu(3.0, 10.0, 2.0, 18.0), Some(300.0));
+        assert_eq!(recompute_cfu(0.0, 10.0, 1.0, 9.0), Some(0.0));
+        assert_eq!(recompute_cfu(12.0, 10.0, 0.0, 9.0), None);
+    }
+
+    #[test]
+    fn tolerance_is_inclusive() {
+        assert_eq!(check(1100.0, 1000.0, 0.1), qc("Ok"));
+        assert_eq!(check(900.0, 1000.0, 0.1), qc("Ok"));
+        assert_eq!(check(1101.0, 1000.0, 0.1), qc("Mismatch"));
+        assert_eq!(check(899.0, 1000.0, 0.1), qc("Mismatch"));
+        assert_eq!(check(1000.0, 1000.0, 0.0), qc("Ok"));
+        assert_eq!(check(1001.0, 1000.0, 0.0), qc("Mismatch"));
+    }
+
+    #[test]
+    fn zero_count() {
+        assert_eq!(check(0.0, 0.0, 0.1), qc("Ok"));
+        assert_eq!(check(10.0, 0.0, 0.1), qc("Mismatch"));
+    }
+
+    /// Run the checks of `test_type`'s extractor over one row holding `cells`.
+    fn check_template(test_type: &str, cells: &[(&str, DataType)]) -> Vec<(String, DataType)> {
+        let extractor = get_extractor(test_type).unwrap();
+        let mut header: Vec<String> = cells.iter().map(|(h, _)| h.to_string()).collect();
+        let mut rows = vec![cells.iter().map(|(_, v)| v.clone()).collect()];
+        check_cfu(&mut header, &mut rows, &extractor.cfu_checks, 0.1);
+        header.into_iter().zip(rows.remove(0)).collect()
+    }
+
+    #[test]
+    fn agricor_micro_row() {
+        let row = check_template(
+            "agricor_micro",
+            &[
+                ("Sample Weight (g)", DataType::Int(1)),
+                ("Diluent Vol (mL)", DataType::Int(9)),
+                ("Colony Count", DataType::Int(12)),
+                ("Dilution Plate", DataType::Int(10)),
+                ("Reported CFU/g", DataType::Int(1200)),
+            ],
+        );
+        assert_eq!(row[5], ("Reported CFU/g Recomputed".to_string(), DataType::Float(1200.0)));
+        assert_eq!(row[6], ("Reported CFU/g QC".to_string(), qc("Ok")));
+    }
+
+    #[test]
+    fn botanacor_micro_row() {
+        let row = check_template(
+            "botanacor_micro",
+            &[
+                ("PBST wt (g)", DataType::Float(10.0)),
+                ("PBST vol (mL)", DataType::Float(90.0)),
+                ("TYM CFU Count", DataType::Int(25)),
+                ("TYM Dil. Plate", DataType::Int(10)),
+                ("TYM Reported CFU/g", DataType::Int(2500)),
+                ("TA CFU Count", DataType::Int(4)),
+                ("TA Dil. Plate", DataType::Int(100)),
+                // transcribed with a digit missing
+                ("TA Reported CFU/g", DataType::Int(400)),
+            ],
+        );
+        let value = |name: &str| row.iter().find(|(h, _)| h == name).map(|(_, v)| v.clone());
+        assert_eq!(value("TYM Reported CFU/g Recomputed"), Some(DataType::Float(2500.0)));
+        assert_eq!(value("TYM Reported CFU/g QC"), Some(qc("Ok")));
+        assert_eq!(value("TA Reported CFU/g Recomputed"), Some(DataType::Float(4000.0)));
+        assert_eq!(value("TA Reported CFU/g QC"), Some(qc("Mismatch")));
+        // the coliform columns are not in the row, so they are not checked
+        assert_eq!(value("Coliforms Reported CFU/g QC"), None);
+    }
+}
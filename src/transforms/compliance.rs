@@ -0,0 +1,519 @@
+use std::fs;
+use std::path::Path;
+
+use calamine::DataType;
+
+use super::{conversion_factor, numeric_value, Analyte, Qualifier, UnitSource, UnitSpec};
+use crate::util::{csv_fields, hash_bytes};
+
+/// Columns a limits table must start with, in order.
+const LIMITS_HEADER: &[&str] = &[
+    "Jurisdiction",
+    "Analyte",
+    "Sample Type",
+    "Action Limit",
+    "Unit",
+];
+
+/// An action limit for one analyte in one jurisdiction.
+struct Limit {
+    analyte: String,
+    /// Sample type the limit applies to, or `None` for any sample type.
+    sample_type: Option<String>,
+    value: f64,
+    /// Unit of `value`, or `None` when it is in the unit the results are reported in.
+    unit: Option<String>,
+}
+
+/// The action limits of one jurisdiction, read from a CSV limits table.
+///
+/// The table has the columns "Jurisdiction", "Analyte", "Sample Type", "Action Limit" and
+/// "Unit". An analyte is listed under its `Analyte::limit_name`: the name of its result column,
+/// e.g. "Acephate", unless the extractor declares another, e.g. "Total Yeast and Mold" for
+/// "TYM Reported CFU/g". A sample type of "*" or an empty one applies to every sample type that
+/// has no limit of its own, and an empty unit is the unit results are reported in.
+pub struct LimitsTable {
+    pub jurisdiction: String,
+    limits: Vec<Limit>,
+    /// Hash of the table's contents, so output evaluated against an edited table is replaced.
+    pub fingerprint: u64,
+}
+
+impl LimitsTable {
+    pub fn load(path: &Path, jurisdiction: &str) -> Result<Self, String> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("Could not read limits table {}: {}", path.display(), e))?;
+        Self::parse(&contents, jurisdiction)
+            .map_err(|e| format!("Limits table {} {}", path.display(), e))
+    }
+
+    /// Read the limits of `jurisdiction` from the contents of a limits table.
+    fn parse(contents: &str, jurisdiction: &str) -> Result<Self, String> {
+        let mut lines = contents.lines().enumerate();
+        let header = lines.next().map_or(vec![], |(_, line)| fields(line));
+        if header.len() < LIMITS_HEADER.len()
+            || !header.iter().zip(LIMITS_HEADER).all(|(h, e)| h.eq_ignore_ascii_case(e))
+        {
+            return Err(format!("must start with the columns {}", LIMITS_HEADER.join(",")));
+        }
+
+        let mut limits = vec![];
+        for (line_idx, line) in lines {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let fields = fields(line);
+            if fields.len() < LIMITS_HEADER.len() {
+                return Err(format!("has too few columns on line {}", line_idx + 1));
+            }
+            if !fields[0].eq_ignore_ascii_case(jurisdiction) {
+                continue;
+            }
+            let value = fields[3].parse().map_err(|_| {
+                format!(
+                    "has an action limit that is not a number on line {}: '{}'",
+                    line_idx + 1,
+                    fields[3]
+                )
+            })?;
+            let non_empty = |s: &str| if s.is_empty() { None } else { Some(s.to_string()) };
+            limits.push(Limit {
+                analyte: fields[1].to_string(),
+                sample_type: non_empty(&fields[2]).filter(|s| s != "*"),
+                value,
+                unit: non_empty(&fields[4]),
+            });
+        }
+        if limits.is_empty() {
+            return Err(format!("has no limits for jurisdiction '{}'", jurisdiction));
+        }
+        Ok(LimitsTable {
+            jurisdiction: jurisdiction.to_string(),
+            limits,
+            fingerprint: hash_bytes(contents.as_bytes()),
+        })
+    }
+
+    /// Limit names of those of `analytes` with a limit for a particular sample type.
+    pub fn sample_type_limits(&self, analytes: &[Analyte]) -> Vec<&'static str> {
+        analytes
+            .iter()
+            .map(Analyte::limit_name)
+            .filter(|name| {
+                self.limits
+                    .iter()
+                    .any(|l| l.sample_type.is_some() && l.analyte.eq_ignore_ascii_case(name))
+            })
+            .collect()
+    }
+
+    /// The limit of `analyte` for samples of `sample_type`, preferring one declared for the
+    /// sample type over one for any sample type.
+    fn limit_for(&self, analyte: &str, sample_type: Option<&str>) -> Option<&Limit> {
+        let for_analyte = |l: &&Limit| l.analyte.eq_ignore_ascii_case(analyte);
+        self.limits
+            .iter()
+            .filter(for_analyte)
+            .find(|l| match (&l.sample_type, sample_type) {
+                (Some(limit_type), Some(sample_type)) => {
+                    limit_type.eq_ignore_ascii_case(sample_type.trim())
+                }
+                _ => false,
+            })
+            .or_else(|| {
+                self.limits
+                    .iter()
+                    .filter(for_analyte)
+                    .find(|l| l.sample_type.is_none())
+            })
+    }
+}
+
+/// The unquoted, trimmed fields of a line of CSV.
+fn fields(line: &str) -> Vec<String> {
+    csv_fields(line).map(|field| field.trim().to_string()).collect()
+}
+
+/// Outcome of comparing a result, or a whole sample, with its action limits.
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub enum Status {
+    Pass,
+    /// Needs review: the result cannot be compared with its limit.
+    Flag,
+    Fail,
+}
+
+impl Status {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Status::Pass => "Pass",
+            Status::Flag => "Flag",
+            Status::Fail => "Fail",
+        }
+    }
+}
+
+/// The output columns compliance is evaluated on.
+pub struct ComplianceColumns<'a> {
+    pub analytes: &'a [Analyte],
+    /// Column holding each sample's type, if the extractor reads one.
+    pub sample_type: Option<&'a str>,
+    pub units: Option<&'a UnitSpec>,
+}
+
+/// Compare each analyte result with its action limit in `table`, adding an "<analyte> Status"
+/// column after the analyte's other columns and a "Sample Status" column at the end of each row.
+///
+/// A result passes when it is at or below its limit or was not detected, fails when it is
+/// above it, and is flagged when it cannot be compared, e.g. a count too numerous to count or a
+/// unit that cannot be converted. A result below the limit of quantitation, such as "< 10", is
+/// compared by its bound, or by its LLOQ column when it has no number, and is never failed.
+/// Results without a limit, and empty results, get no status. The sample status is the worst
+/// of its results' statuses.
+pub fn evaluate_compliance(
+    header: &mut Vec<String>,
+    rows: &mut [Vec<DataType>],
+    table: &LimitsTable,
+    columns: &ComplianceColumns,
+) {
+    let position = |header: &[String], name: &str| header.iter().position(|h| h == name);
+    let sample_type_idx = columns.sample_type.and_then(|c| position(header, c));
+    let unit_idx = position(header, "Normalized Unit").or_else(|| match columns.units {
+        Some(UnitSpec {
+            source: UnitSource::Column(name),
+            ..
+        }) => position(header, name),
+        _ => None,
+    });
+    let fixed_unit = match columns.units {
+        Some(UnitSpec {
+            source: UnitSource::Fixed(unit),
+            ..
+        }) => Some(unit.to_string()),
+        _ => None,
+    };
+    // read before status columns are inserted, which moves the columns after them
+    let text = |row: &[DataType], idx: Option<usize>| match idx.map(|i| &row[i]) {
+        Some(DataType::String(s)) => Some(s.clone()),
+        _ => None,
+    };
+    let sample_types: Vec<Option<String>> =
+        rows.iter().map(|row| text(row, sample_type_idx)).collect();
+    let units: Vec<Option<String>> = rows
+        .iter()
+        .map(|row| text(row, unit_idx).or_else(|| fixed_unit.clone()))
+        .collect();
+
+    let mut sample_statuses: Vec<Option<Status>> = vec![None; rows.len()];
+    for analyte in columns.analytes.iter() {
+        let result_idx = match position(header, analyte.result) {
+            Some(idx) => idx,
+            None => continue,
+        };
+        let qualifier_idx = position(header, &format!("{} Qualifier", analyte.result));
+        let lloq_idx = position(header, &format!("{} LLOQ", analyte.result));
+        // after the result and the columns added for it by earlier transforms
+        let insert_idx = ["Qualifier", "LLOQ", "ULOQ", "Action Limit", "Flag"]
+            .iter()
+            .filter_map(|suffix| position(header, &format!("{} {}", analyte.result, suffix)))
+            .fold(result_idx, usize::max)
+            + 1;
+
+        for (row_idx, row) in rows.iter_mut().enumerate() {
+            let status = table
+                .limit_for(analyte.limit_name(), sample_types[row_idx].as_deref())
+                .and_then(|limit| {
+                    let result = QualifiedResult {
+                        value: &row[result_idx],
+                        qualifier: qualifier_idx.map(|i| &row[i]),
+                        lloq: lloq_idx.and_then(|i| numeric_value(&row[i])),
+                    };
+                    result_status(&result, limit, units[row_idx].as_deref())
+                });
+            let sample_status = &mut sample_statuses[row_idx];
+            if let Some(status) = status {
+                if sample_status.is_none_or(|s| status > s) {
+                    *sample_status = Some(status);
+                }
+            }
+            row.insert(
+                insert_idx,
+                status.map_or(DataType::Empty, |s| DataType::String(s.as_str().to_string())),
+            );
+        }
+        header.insert(insert_idx, format!("{} Status", analyte.result));
+    }
+
+    for (row, status) in rows.iter_mut().zip(sample_statuses) {
+        row.push(status.map_or(DataType::Empty, |s| DataType::String(s.as_str().to_string())));
+    }
+    header.push("Sample Status".to_string());
+}
+
+/// The cells of one analyte result in a row.
+struct QualifiedResult<'a> {
+    value: &'a DataType,
+    qualifier: Option<&'a DataType>,
+    /// Lower limit of quantitation, the bound of a "< LOQ" result without a number.
+    lloq: Option<f64>,
+}
+
+fn result_status(result: &QualifiedResult, limit: &Limit, unit: Option<&str>) -> Option<Status> {
+    let qualifier = match result.qualifier {
+        Some(DataType::String(q)) => Some(q.as_str()),
+        _ => None,
+    };
+    let is_qualified = |q: Qualifier| qualifier == Some(q.as_str());
+    if is_qualified(Qualifier::NotDetected) {
+        return Some(Status::Pass);
+    }
+    let below_loq = is_qualified(Qualifier::BelowLoq);
+    // for a qualified result the value is a bound: the true value is below or above it
+    let value = match (numeric_value(result.value), result.value) {
+        (Some(value), _) => value,
+        (None, _) if below_loq => match result.lloq {
+            Some(lloq) => lloq,
+            None => return Some(Status::Flag),
+        },
+        (None, DataType::Empty) if qualifier.is_none() => return None,
+        _ => return Some(Status::Flag),
+    };
+    let factor = match (&limit.unit, unit) {
+        (None, _) => Some(1.0),
+        (Some(limit_unit), Some(unit)) => conversion_factor(limit_unit, unit),
+        (Some(_), None) => None,
+    };
+    let limit_value = match factor {
+        Some(factor) => limit.value * factor,
+        None => return Some(Status::Flag),
+    };
+    Some(if below_loq {
+        // only known to be under its bound, so it is never failed
+        if value <= limit_value {
+            Status::Pass
+        } else {
+            Status::Flag
+        }
+    } else if is_qualified(Qualifier::AboveUloq) {
+        // only known to be over its bound
+        if value >= limit_value {
+            Status::Fail
+        } else {
+            Status::Flag
+        }
+    } else if value > limit_value {
+        Status::Fail
+    } else {
+        Status::Pass
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transforms::split_qualified_results;
+
+    fn limit(value: f64, unit: Option<&str>) -> Limit {
+        Limit {
+            analyte: "Acephate".to_string(),
+            sample_type: None,
+            value,
+            unit: unit.map(String::from),
+        }
+    }
+
+    /// Status of a raw result cell, qualified as `split_qualified_results` would qualify it.
+    fn status(cell: &str, limit: &Limit) -> Option<Status> {
+        let mut header = vec!["Acephate".to_string()];
+        let cell = match cell {
+            "" => DataType::Empty,
+            _ => DataType::String(cell.to_string()),
+        };
+        let mut rows = vec![vec![cell]];
+        split_qualified_results(&mut header, &mut rows, &["Acephate"]);
+        let result = QualifiedResult {
+            value: &rows[0][0],
+            qualifier: Some(&rows[0][1]),
+            lloq: None,
+        };
+        result_status(&result, limit, None)
+    }
+
+    #[test]
+    fn plain_results() {
+        assert_eq!(status("5", &limit(10.0, None)), Some(Status::Pass));
+        assert_eq!(status("10", &limit(10.0, None)), Some(Status::Pass));
+        assert_eq!(status("11", &limit(10.0, None)), Some(Status::Fail));
+        assert_eq!(status("", &limit(10.0, None)), None);
+        assert_eq!(status("NA", &limit(10.0, None)), Some(Status::Flag));
+    }
+
+    #[test]
+    fn not_detected_passes() {
+        assert_eq!(status("ND", &limit(10.0, None)), Some(Status::Pass));
+        assert_eq!(status("ND", &limit(0.0, None)), Some(Status::Pass));
+    }
+
+    #[test]
+    fn below_loq_is_never_failed() {
+        assert_eq!(status("< 10", &limit(20.0, None)), Some(Status::Pass));
+        assert_eq!(status("< 10", &limit(10.0, None)), Some(Status::Pass));
+        assert_eq!(status("< 10", &limit(5.0, None)), Some(Status::Flag));
+        // no bound to compare
+        assert_eq!(status("<LOQ", &limit(5.0, None)), Some(Status::Flag));
+    }
+
+    #[test]
+    fn below_loq_without_number_uses_lloq() {
+        let qualifier = DataType::String(Qualifier::BelowLoq.as_str().to_string());
+        let result = |lloq| QualifiedResult {
+            value: &DataType::Empty,
+            qualifier: Some(&qualifier),
+            lloq,
+        };
+        let limit = limit(5.0, None);
+        assert_eq!(result_status(&result(Some(2.0)), &limit, None), Some(Status::Pass));
+        assert_eq!(result_status(&result(Some(8.0)), &limit, None), Some(Status::Flag));
+    }
+
+    #[test]
+    fn above_uloq_fails_only_over_the_limit() {
+        assert_eq!(status("> 10", &limit(5.0, None)), Some(Status::Fail));
+        assert_eq!(status("> 10", &limit(10.0, None)), Some(Status::Fail));
+        assert_eq!(status("> 10", &limit(20.0, None)), Some(Status::Flag));
+    }
+
+    #[test]
+    fn too_numerous_to_count_is_flagged() {
+        assert_eq!(status("TNTC", &limit(10.0, None)), Some(Status::Flag));
+        assert_eq!(status("TNTC", &limit(1e9, None)), Some(Status::Flag));
+    }
+
+    #[test]
+    fn limits_are_converted_into_the_result_unit() {
+        let result = QualifiedResult {
+            value: &DataType::Float(0.1),
+            qualifier: None,
+            lloq: None,
+        };
+        let ppb = limit(1.0, Some("ppb"));
+        assert_eq!(result_status(&result, &ppb, Some("ppm")), Some(Status::Fail));
+        let ppm = limit(0.5, Some("ppm"));
+        assert_eq!(result_status(&result, &ppm, Some("ug/g")), Some(Status::Pass));
+        assert_eq!(result_status(&result, &ppm, None), Some(Status::Flag));
+        assert_eq!(result_status(&result, &ppm, Some("CFU/g")), Some(Status::Flag));
+    }
+
+    #[test]
+    fn sample_status_is_the_worst_result() {
+        let table = LimitsTable {
+            jurisdiction: "CO".to_string(),
+            limits: vec![
+                limit(10.0, None),
+                Limit {
+                    analyte: "Oxamyl".to_string(),
+                    sample_type: Some("Flower".to_string()),
+                    ..limit(1.0, None)
+                },
+            ],
+            fingerprint: 0,
+        };
+        let analytes = [Analyte::new("Acephate"), Analyte::new("Oxamyl")];
+        let mut header: Vec<String> =
+            ["Sample Type", "Acephate", "Oxamyl"].iter().map(|h| h.to_string()).collect();
+        let cell = |s: &str| DataType::String(s.to_string());
+        let mut rows = vec![
+            vec![cell("Flower"), DataType::Float(5.0), DataType::Float(2.0)],
+            vec![cell("Concentrate"), DataType::Float(5.0), DataType::Float(2.0)],
+        ];
+        let columns = ComplianceColumns {
+            analytes: &analytes,
+            sample_type: Some("Sample Type"),
+            units: None,
+        };
+        evaluate_compliance(&mut header, &mut rows, &table, &columns);
+        assert_eq!(
+            header,
+            [
+                "Sample Type",
+                "Acephate",
+                "Acephate Status",
+                "Oxamyl",
+                "Oxamyl Status",
+                "Sample Status"
+            ]
+        );
+        assert_eq!(rows[0][4], cell("Fail"));
+        assert_eq!(rows[0][5], cell("Fail"));
+        // Oxamyl has no limit for concentrates
+        assert_eq!(rows[1][4], DataType::Empty);
+        assert_eq!(rows[1][5], cell("Pass"));
+    }
+
+    #[test]
+    fn parse_limits_table() {
+        let contents = "Jurisdiction,Analyte,Sample Type,Action Limit,Unit\r\n\
+                        CO,\"Spinosad A, D\",*,0.1,ppm\r\n\
+                        \r\n\
+                        \"co\" , Total Yeast and Mold , Flower , 10000 ,\r\n\
+                        OR,Acephate,,0.01,ppm\r\n";
+        let table = LimitsTable::parse(contents, "CO").unwrap();
+        assert_eq!(table.limits.len(), 2);
+        let spinosad = table.limit_for("spinosad a, d", Some("Edible")).unwrap();
+        assert_eq!((spinosad.value, spinosad.unit.as_deref()), (0.1, Some("ppm")));
+        let tym = table.limit_for("Total Yeast and Mold", Some(" flower")).unwrap();
+        assert_eq!((tym.value, tym.unit.as_deref()), (10000.0, None));
+        assert!(table.limit_for("Total Yeast and Mold", Some("Edible")).is_none());
+        assert!(table.limit_for("Acephate", None).is_none());
+    }
+
+    #[test]
+    fn invalid_limits_tables() {
+        let parse = |contents: &str| LimitsTable::parse(contents, "CO").map(|_| ());
+        assert!(parse("").is_err());
+        assert!(parse("Jurisdiction,Analyte,Action Limit\nCO,Acephate,1").is_err());
+        let header = "Jurisdiction,Analyte,Sample Type,Action Limit,Unit\n";
+        assert!(parse(&format!("{}CO,Acephate,*,0.4", header)).is_err());
+        assert!(parse(&format!("{}CO,Acephate,*,high,ppm", header)).is_err());
+        assert!(parse(&format!("{}OR,Acephate,*,0.4,ppm", header)).is_err());
+        assert!(parse(&format!("{}CO,Acephate,*,0.4,ppm", header)).is_ok());
+    }
+
+    #[test]
+    fn analytes_are_looked_up_by_limit_name() {
+        let contents = "Jurisdiction,Analyte,Sample Type,Action Limit,Unit\n\
+                        CO,Total Yeast and Mold,Flower,10000,CFU/g\n\
+                        CO,Total Aerobic Count,*,100000,CFU/g\n";
+        let table = LimitsTable::parse(contents, "CO").unwrap();
+        let analytes = [
+            Analyte::named("TYM Reported CFU/g", "Total Yeast and Mold"),
+            Analyte::named("TA Reported CFU/g", "Total Aerobic Count"),
+        ];
+        assert_eq!(table.sample_type_limits(&analytes), ["Total Yeast and Mold"]);
+
+        let mut header: Vec<String> = ["Sample Type", "TYM Reported CFU/g", "TA Reported CFU/g"]
+            .iter()
+            .map(|h| h.to_string())
+            .collect();
+        let mut rows = vec![vec![
+            DataType::String("Flower".to_string()),
+            DataType::Float(20000.0),
+            DataType::Float(500.0),
+        ]];
+        let columns = ComplianceColumns {
+            analytes: &analytes,
+            sample_type: Some("Sample Type"),
+            units: Some(&UnitSpec {
+                source: UnitSource::Fixed("CFU/g"),
+                canonical: "CFU/g",
+            }),
+        };
+        evaluate_compliance(&mut header, &mut rows, &table, &columns);
+        let status = |name: &str| {
+            let idx = header.iter().position(|h| h == name).unwrap();
+            rows[0][idx].clone()
+        };
+        assert_eq!(status("TYM Reported CFU/g Status"), DataType::String("Fail".to_string()));
+        assert_eq!(status("TA Reported CFU/g Status"), DataType::String("Pass".to_string()));
+    }
+}
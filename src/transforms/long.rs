@@ -0,0 +1,247 @@
+use calamine::DataType;
+
+use super::{Analyte, UnitSource, UnitSpec};
+
+/// Columns of long output, which has one row per sample and analyte.
+pub const LONG_HEADER: &[&str] = &[
+    "Test ID",
+    "Sample Name",
+    "Sample Type",
+    "Run Date",
+    "Batch",
+    "Analyte",
+    "Value",
+    "Qualifier",
+    "Unit",
+    "LLOQ",
+    "ULOQ",
+    "Action Limit",
+    "Flag",
+    "Status",
+    "Sample Status",
+    "QC",
+    "File Path",
+];
+
+/// The output columns that identify a sample and hold its analyte results.
+pub struct LongColumns<'a> {
+    pub test_id: &'a str,
+    pub sample_name: &'a str,
+    pub sample_type: Option<&'a str>,
+    pub analytes: &'a [Analyte],
+    pub units: Option<&'a UnitSpec>,
+}
+
+/// Positions of an analyte's columns in the wide header.
+struct AnalyteCols<'a> {
+    analyte: &'a str,
+    value: Option<usize>,
+    qualifier: Option<usize>,
+    lloq: Option<usize>,
+    uloq: Option<usize>,
+    action_limit: Option<usize>,
+    flag: Option<usize>,
+    status: Option<usize>,
+    qc: Option<usize>,
+}
+
+/// Unpivot every row into one row per analyte, laid out as `LONG_HEADER`.
+///
+/// Each row keeps the sample's identifying columns, its sample type and the run date and batch
+/// of the file it came from.
+/// Limits, flags, statuses and CFU checks are read from the columns written by `attach_limits`,
+/// `evaluate_compliance` and `check_cfu`, and the unit from the
+/// "Normalized Unit" column when units have been normalized. A column missing from `header`
+/// leaves its cells empty.
+pub fn unpivot(header: &mut Vec<String>, rows: &mut Vec<Vec<DataType>>, columns: &LongColumns) {
+    let position = |name: &str| header.iter().position(|h| h == name);
+    let test_id_idx = position(columns.test_id);
+    let sample_name_idx = position(columns.sample_name);
+    let sample_type_idx = columns.sample_type.and_then(position);
+    let run_date_idx = position("Run Date");
+    let batch_idx = position("Batch");
+    let sample_status_idx = position("Sample Status");
+    let file_path_idx = position("File Path");
+    let unit_idx = position("Normalized Unit").or_else(|| match columns.units {
+        Some(UnitSpec {
+            source: UnitSource::Column(name),
+            ..
+        }) => position(name),
+        _ => None,
+    });
+    let fixed_unit = match columns.units {
+        Some(UnitSpec {
+            source: UnitSource::Fixed(unit),
+            ..
+        }) if unit_idx.is_none() => DataType::String(unit.to_string()),
+        _ => DataType::Empty,
+    };
+    let analyte_cols: Vec<AnalyteCols> = columns
+        .analytes
+        .iter()
+        .map(|analyte| {
+            let column = |suffix: &str| position(&format!("{} {}", analyte.result, suffix));
+            AnalyteCols {
+                analyte: analyte.result,
+                value: position(analyte.result),
+                qualifier: column("Qualifier"),
+                lloq: column("LLOQ"),
+                uloq: column("ULOQ"),
+                action_limit: column("Action Limit"),
+                flag: column("Flag"),
+                status: column("Status"),
+                qc: column("QC"),
+            }
+        })
+        .collect();
+
+    let cell =
+        |row: &[DataType], idx: Option<usize>| idx.map_or(DataType::Empty, |i| row[i].clone());
+    *rows = rows
+        .iter()
+        .flat_map(|row| {
+            let unit = unit_idx.map_or(fixed_unit.clone(), |i| row[i].clone());
+            analyte_cols
+                .iter()
+                .map(|cols| {
+                    vec![
+                        cell(row, test_id_idx),
+                        cell(row, sample_name_idx),
+                        cell(row, sample_type_idx),
+                        cell(row, run_date_idx),
+                        cell(row, batch_idx),
+                        DataType::String(cols.analyte.to_string()),
+                        cell(row, cols.value),
+                        cell(row, cols.qualifier),
+                        unit.clone(),
+                        cell(row, cols.lloq),
+                        cell(row, cols.uloq),
+                        cell(row, cols.action_limit),
+                        cell(row, cols.flag),
+                        cell(row, cols.status),
+                        cell(row, sample_status_idx),
+                        cell(row, cols.qc),
+                        cell(row, file_path_idx),
+                    ]
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect();
+    *header = LONG_HEADER.iter().map(|h| h.to_string()).collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(s: &str) -> DataType {
+        DataType::String(s.to_string())
+    }
+
+    fn wide(names: &[&str]) -> Vec<String> {
+        names.iter().map(|h| h.to_string()).collect()
+    }
+
+    fn long_cell<'a>(header: &[String], row: &'a [DataType], name: &str) -> &'a DataType {
+        &row[header.iter().position(|h| h == name).unwrap()]
+    }
+
+    #[test]
+    fn one_row_per_sample_and_analyte() {
+        let mut header = wide(&[
+            "Test ID", "Sample Name", "Sample Type", "CBDa", "THCa", "Run Date", "Batch",
+            "File Path",
+        ]);
+        let sample = |id: &str, cbda: f64| {
+            vec![
+                text(id),
+                text("Hemp"),
+                text("Flower"),
+                DataType::Float(cbda),
+                DataType::Float(0.1),
+                text("2020-03-02"),
+                text("B12"),
+                text("a.xlsm"),
+            ]
+        };
+        let mut rows = vec![sample("T-1", 5.0), sample("T-2", 6.0)];
+        let analytes = [Analyte::new("CBDa"), Analyte::new("THCa"), Analyte::new("CBG")];
+        let columns = LongColumns {
+            test_id: "Test ID",
+            sample_name: "Sample Name",
+            sample_type: Some("Sample Type"),
+            analytes: &analytes,
+            units: Some(&UnitSpec {
+                source: UnitSource::Fixed("%"),
+                canonical: "%",
+            }),
+        };
+        unpivot(&mut header, &mut rows, &columns);
+
+        assert_eq!(header, LONG_HEADER);
+        assert_eq!(rows.len(), 2 * analytes.len());
+        let second = &rows[3];
+        assert_eq!(long_cell(&header, second, "Test ID"), &text("T-2"));
+        assert_eq!(long_cell(&header, second, "Analyte"), &text("CBDa"));
+        assert_eq!(long_cell(&header, second, "Value"), &DataType::Float(6.0));
+        assert_eq!(long_cell(&header, second, "Sample Type"), &text("Flower"));
+        assert_eq!(long_cell(&header, second, "Run Date"), &text("2020-03-02"));
+        assert_eq!(long_cell(&header, second, "Batch"), &text("B12"));
+        assert_eq!(long_cell(&header, second, "File Path"), &text("a.xlsm"));
+        assert_eq!(long_cell(&header, second, "Unit"), &text("%"));
+        // an analyte without a column still gets its row, with empty cells
+        let missing = &rows[5];
+        assert_eq!(long_cell(&header, missing, "Analyte"), &text("CBG"));
+        assert_eq!(long_cell(&header, missing, "Value"), &DataType::Empty);
+        assert_eq!(long_cell(&header, missing, "LLOQ"), &DataType::Empty);
+    }
+
+    #[test]
+    fn normalized_unit_replaces_the_declared_unit() {
+        let unpivoted_unit = |names: &[&str], cells: Vec<DataType>| {
+            let mut header = wide(names);
+            let mut rows = vec![cells];
+            let analytes = [Analyte::new("Acephate")];
+            let columns = LongColumns {
+                test_id: "Test ID",
+                sample_name: "Sample Name",
+                sample_type: None,
+                analytes: &analytes,
+                units: Some(&UnitSpec {
+                    source: UnitSource::Fixed("ppm"),
+                    canonical: "ug/g",
+                }),
+            };
+            unpivot(&mut header, &mut rows, &columns);
+            long_cell(&header, &rows[0], "Unit").clone()
+        };
+        assert_eq!(unpivoted_unit(&["Acephate"], vec![DataType::Float(0.2)]), text("ppm"));
+        assert_eq!(
+            unpivoted_unit(
+                &["Acephate", "Normalized Unit"],
+                vec![DataType::Float(0.2), text("ug/g")]
+            ),
+            text("ug/g")
+        );
+    }
+
+    #[test]
+    fn unit_column() {
+        let mut header = wide(&["Unit", "CBDa"]);
+        let mut rows = vec![vec![text("mg/g"), DataType::Float(50.0)]];
+        let analytes = [Analyte::new("CBDa")];
+        let columns = LongColumns {
+            test_id: "Test ID",
+            sample_name: "Sample Name",
+            sample_type: None,
+            analytes: &analytes,
+            units: Some(&UnitSpec {
+                source: UnitSource::Column("Unit"),
+                canonical: "%",
+            }),
+        };
+        unpivot(&mut header, &mut rows, &columns);
+        assert_eq!(long_cell(&header, &rows[0], "Unit"), &text("mg/g"));
+        assert_eq!(long_cell(&header, &rows[0], "Test ID"), &DataType::Empty);
+    }
+}
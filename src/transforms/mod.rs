@@ -0,0 +1,13 @@
+mod cfu;
+mod compliance;
+mod long;
+mod panel;
+mod qualifiers;
+mod units;
+
+pub use cfu::*;
+pub use compliance::*;
+pub use long::*;
+pub use panel::*;
+pub use qualifiers::*;
+pub use units::*;
@@ -0,0 +1,229 @@
+use calamine::DataType;
+
+use super::numeric_value;
+
+/// An analyte result column and the output columns holding its limits.
+#[derive(Clone, Default)]
+pub struct Analyte {
+    /// Output column holding the result.
+    pub result: &'static str,
+    /// Name the analyte is listed under in a limits table, when it is not the result column's
+    /// name, e.g. "Total Yeast and Mold" for "TYM Reported CFU/g".
+    pub name: Option<&'static str>,
+    /// Output column holding the lower limit of quantitation.
+    pub lloq: Option<&'static str>,
+    /// Output column holding the upper limit of quantitation.
+    pub uloq: Option<&'static str>,
+    /// Output column holding the level a result must not exceed.
+    pub action_limit: Option<&'static str>,
+}
+
+impl Analyte {
+    /// An analyte with no limits.
+    pub fn new(result: &'static str) -> Self {
+        Analyte {
+            result,
+            ..Default::default()
+        }
+    }
+
+    /// An analyte with no limits, listed as `name` in a limits table.
+    pub fn named(result: &'static str, name: &'static str) -> Self {
+        Analyte {
+            result,
+            name: Some(name),
+            ..Default::default()
+        }
+    }
+
+    /// Name of the analyte in a limits table.
+    pub fn limit_name(&self) -> &'static str {
+        self.name.unwrap_or(self.result)
+    }
+
+    /// The declared limits, each with the name of the output column it is written to.
+    fn limits(&self) -> Vec<(String, &'static str)> {
+        [
+            ("LLOQ", self.lloq),
+            ("ULOQ", self.uloq),
+            ("Action Limit", self.action_limit),
+        ]
+        .iter()
+        .filter_map(|(kind, source)| source.map(|s| (format!("{} {}", self.result, kind), s)))
+        .collect()
+    }
+
+    /// The output columns holding the result and its limits once `attach_limits` has run.
+    pub fn value_columns(&self) -> Vec<String> {
+        std::iter::once(self.result.to_string())
+            .chain(self.limits().into_iter().map(|(name, _)| name))
+            .collect()
+    }
+}
+
+/// Where a result lies relative to its analyte's limits.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Flag {
+    BelowLloq,
+    AboveUloq,
+    AboveActionLimit,
+}
+
+impl Flag {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Flag::BelowLloq => "BelowLloq",
+            Flag::AboveUloq => "AboveUloq",
+            Flag::AboveActionLimit => "AboveActionLimit",
+        }
+    }
+}
+
+/// Flag a result against its limits, an exceeded action limit taking precedence.
+fn flag_result(
+    value: f64,
+    lloq: Option<f64>,
+    uloq: Option<f64>,
+    action_limit: Option<f64>,
+) -> Option<Flag> {
+    if action_limit.is_some_and(|limit| value > limit) {
+        Some(Flag::AboveActionLimit)
+    } else if uloq.is_some_and(|limit| value > limit) {
+        Some(Flag::AboveUloq)
+    } else if lloq.is_some_and(|limit| value < limit) {
+        Some(Flag::BelowLloq)
+    } else {
+        None
+    }
+}
+
+/// Move each analyte's limit columns directly after its result, and its qualifier if it has
+/// one, named "<analyte> LLOQ", "<analyte> ULOQ" and "<analyte> Action Limit", followed by a
+/// "<analyte> Flag" column flagging the result against them.
+///
+/// Analytes without limits are left as they are. A limit column missing from `header` leaves
+/// its cells empty.
+pub fn attach_limits(header: &mut Vec<String>, rows: &mut [Vec<DataType>], analytes: &[Analyte]) {
+    for analyte in analytes.iter() {
+        let limits = analyte.limits();
+        if limits.is_empty() || !header.iter().any(|h| h == analyte.result) {
+            continue;
+        }
+
+        // (output column name, values) of each limit, taken out of the rows
+        let mut limit_cols: Vec<(String, Vec<DataType>)> = vec![];
+        for (name, source) in limits {
+            let values = match header.iter().position(|h| h == source) {
+                Some(idx) => {
+                    header.remove(idx);
+                    rows.iter_mut().map(|row| row.remove(idx)).collect()
+                }
+                None => vec![DataType::Empty; rows.len()],
+            };
+            limit_cols.push((name, values));
+        }
+
+        let result_idx = header.iter().position(|h| h == analyte.result).unwrap();
+        let qualifier = format!("{} Qualifier", analyte.result);
+        let mut insert_idx = result_idx + 1;
+        if header.get(insert_idx) == Some(&qualifier) {
+            insert_idx += 1;
+        }
+
+        let limit_value = |kind: &str, row_idx: usize| {
+            let name = format!("{} {}", analyte.result, kind);
+            limit_cols
+                .iter()
+                .find(|(n, _)| *n == name)
+                .and_then(|(_, values)| numeric_value(&values[row_idx]))
+        };
+        for (row_idx, row) in rows.iter_mut().enumerate() {
+            let flag = numeric_value(&row[result_idx]).and_then(|value| {
+                flag_result(
+                    value,
+                    limit_value("LLOQ", row_idx),
+                    limit_value("ULOQ", row_idx),
+                    limit_value("Action Limit", row_idx),
+                )
+            });
+            let cells = limit_cols
+                .iter()
+                .map(|(_, values)| values[row_idx].clone())
+                .chain(std::iter::once(
+                    flag.map_or(DataType::Empty, |f| DataType::String(f.as_str().to_string())),
+                ));
+            row.splice(insert_idx..insert_idx, cells);
+        }
+
+        let names = limit_cols
+            .iter()
+            .map(|(name, _)| name.clone())
+            .chain(std::iter::once(format!("{} Flag", analyte.result)));
+        header.splice(insert_idx..insert_idx, names);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flags() {
+        assert_eq!(flag_result(5.0, Some(1.0), Some(10.0), Some(8.0)), None);
+        assert_eq!(flag_result(0.5, Some(1.0), Some(10.0), None), Some(Flag::BelowLloq));
+        assert_eq!(flag_result(11.0, Some(1.0), Some(10.0), None), Some(Flag::AboveUloq));
+        let above_action_limit = Some(Flag::AboveActionLimit);
+        assert_eq!(flag_result(9.0, Some(1.0), Some(10.0), Some(8.0)), above_action_limit);
+        // the action limit takes precedence
+        assert_eq!(flag_result(11.0, None, Some(10.0), Some(8.0)), above_action_limit);
+        assert_eq!(flag_result(0.5, Some(1.0), None, Some(0.1)), above_action_limit);
+        // limits are inclusive
+        assert_eq!(flag_result(1.0, Some(1.0), Some(10.0), Some(10.0)), None);
+        assert_eq!(flag_result(10.0, Some(1.0), Some(10.0), Some(10.0)), None);
+        assert_eq!(flag_result(1e9, None, None, None), None);
+    }
+
+    #[test]
+    fn limits_follow_their_result() {
+        let analytes = [
+            Analyte {
+                result: "CBDa",
+                lloq: Some("LOQ CBDa"),
+                action_limit: Some("Max CBDa"),
+                ..Default::default()
+            },
+            Analyte::new("CBDV"),
+        ];
+        assert_eq!(analytes[0].value_columns(), ["CBDa", "CBDa LLOQ", "CBDa Action Limit"]);
+        assert_eq!(analytes[1].limit_name(), "CBDV");
+
+        let mut header: Vec<String> = ["LOQ CBDa", "CBDa", "CBDa Qualifier", "CBDV", "Batch"]
+            .iter()
+            .map(|h| h.to_string())
+            .collect();
+        let cell = |s: &str| DataType::String(s.to_string());
+        let loq = DataType::Float(0.1);
+        let mut rows = vec![
+            vec![loq.clone(), DataType::Float(0.05), DataType::Empty, cell("1"), cell("A")],
+            vec![loq.clone(), DataType::Empty, cell("NotDetected"), cell("2"), cell("A")],
+        ];
+        attach_limits(&mut header, &mut rows, &analytes);
+
+        assert_eq!(
+            header,
+            [
+                "CBDa",
+                "CBDa Qualifier",
+                "CBDa LLOQ",
+                "CBDa Action Limit",
+                "CBDa Flag",
+                "CBDV",
+                "Batch"
+            ]
+        );
+        assert_eq!(rows[0][2..5], [loq.clone(), DataType::Empty, cell("BelowLloq")]);
+        // no result to flag, and no action limit column to read
+        assert_eq!(rows[1][2..5], [loq, DataType::Empty, DataType::Empty]);
+        assert_eq!(rows[1][5..], [cell("2"), cell("A")]);
+    }
+}
@@ -0,0 +1,148 @@
+use calamine::DataType;
+
+use super::numeric_value;
+
+/// Qualifier attached to a result that is not a plain number.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Qualifier {
+    NotDetected,
+    BelowLoq,
+    AboveUloq,
+    TooNumerousToCount,
+}
+
+impl Qualifier {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Qualifier::NotDetected => "NotDetected",
+            Qualifier::BelowLoq => "BelowLoq",
+            Qualifier::AboveUloq => "AboveUloq",
+            Qualifier::TooNumerousToCount => "TooNumerousToCount",
+        }
+    }
+}
+
+/// Split a result cell into its numeric value and qualifier.
+///
+/// Returns `None` for cells that are neither numbers nor a recognized qualifier string,
+/// e.g. "NA" for a column that could not be found.
+pub fn parse_result(dt: &DataType) -> Option<(Option<f64>, Option<Qualifier>)> {
+    let s = match dt {
+        DataType::Empty => return Some((None, None)),
+        DataType::String(s) => s.trim().to_uppercase(),
+        _ => return numeric_value(dt).map(|v| (Some(v), None)),
+    };
+
+    match s.as_str() {
+        "ND" | "N.D." | "NOT DETECTED" | "NONE DETECTED" => {
+            return Some((None, Some(Qualifier::NotDetected)))
+        }
+        "TNTC" => return Some((None, Some(Qualifier::TooNumerousToCount))),
+        "BLQ" | "BQL" => return Some((None, Some(Qualifier::BelowLoq))),
+        _ => (),
+    }
+
+    let (qualifier, rest) = if let Some(rest) = s.strip_prefix('<') {
+        (Qualifier::BelowLoq, rest)
+    } else if let Some(rest) = s.strip_prefix('>') {
+        (Qualifier::AboveUloq, rest)
+    } else {
+        return s.parse().ok().map(|v| (Some(v), None));
+    };
+    let rest = rest.trim();
+    match rest {
+        "" | "LOQ" | "LLOQ" | "ULOQ" => Some((None, Some(qualifier))),
+        _ => rest.parse().ok().map(|v| (Some(v), Some(qualifier))),
+    }
+}
+
+/// Replace each of `columns` with its numeric value and insert a "<column> Qualifier"
+/// column directly after it.
+///
+/// Cells that cannot be parsed are left as they are with an empty qualifier.
+pub fn split_qualified_results(
+    header: &mut Vec<String>,
+    rows: &mut [Vec<DataType>],
+    columns: &[&'static str],
+) {
+    for column in columns.iter() {
+        let idx = match header.iter().position(|h| h == column) {
+            Some(idx) => idx,
+            None => continue,
+        };
+        for row in rows.iter_mut() {
+            let qualifier = match parse_result(&row[idx]) {
+                Some((value, qualifier)) => {
+                    row[idx] = value.map_or(DataType::Empty, DataType::Float);
+                    qualifier.map_or(DataType::Empty, |q| DataType::String(q.as_str().to_string()))
+                }
+                None => DataType::Empty,
+            };
+            row.insert(idx + 1, qualifier);
+        }
+        header.insert(idx + 1, format!("{} Qualifier", column));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> Option<(Option<f64>, Option<Qualifier>)> {
+        parse_result(&DataType::String(s.to_string()))
+    }
+
+    #[test]
+    fn plain_numbers() {
+        assert_eq!(parse_result(&DataType::Float(1.5)), Some((Some(1.5), None)));
+        assert_eq!(parse_result(&DataType::Int(3)), Some((Some(3.0), None)));
+        assert_eq!(parse(" 0.25 "), Some((Some(0.25), None)));
+        assert_eq!(parse_result(&DataType::Empty), Some((None, None)));
+    }
+
+    #[test]
+    fn qualifier_words() {
+        for nd in ["ND", "nd", "N.D.", "Not Detected", " none detected "] {
+            assert_eq!(parse(nd), Some((None, Some(Qualifier::NotDetected))), "{}", nd);
+        }
+        assert_eq!(parse("tntc"), Some((None, Some(Qualifier::TooNumerousToCount))));
+        assert_eq!(parse("BLQ"), Some((None, Some(Qualifier::BelowLoq))));
+        assert_eq!(parse("BQL"), Some((None, Some(Qualifier::BelowLoq))));
+    }
+
+    #[test]
+    fn bounds() {
+        assert_eq!(parse("<10"), Some((Some(10.0), Some(Qualifier::BelowLoq))));
+        assert_eq!(parse("< 0.5"), Some((Some(0.5), Some(Qualifier::BelowLoq))));
+        assert_eq!(parse("> 3"), Some((Some(3.0), Some(Qualifier::AboveUloq))));
+        assert_eq!(parse("<LOQ"), Some((None, Some(Qualifier::BelowLoq))));
+        assert_eq!(parse("< lloq"), Some((None, Some(Qualifier::BelowLoq))));
+        assert_eq!(parse(">ULOQ"), Some((None, Some(Qualifier::AboveUloq))));
+        assert_eq!(parse("<"), Some((None, Some(Qualifier::BelowLoq))));
+    }
+
+    #[test]
+    fn unrecognized_cells() {
+        assert_eq!(parse("NA"), None);
+        assert_eq!(parse("< ten"), None);
+        assert_eq!(parse("12 CFU"), None);
+    }
+
+    #[test]
+    fn split_adds_a_qualifier_column() {
+        let mut header = vec!["Test ID".to_string(), "Acephate".to_string(), "Run".to_string()];
+        let cell = |s: &str| DataType::String(s.to_string());
+        let mut rows = vec![
+            vec![cell("T-1"), cell("< 10"), cell("A")],
+            vec![cell("T-2"), DataType::Float(2.0), cell("A")],
+            vec![cell("T-3"), cell("NA"), cell("A")],
+        ];
+        split_qualified_results(&mut header, &mut rows, &["Acephate", "Missing"]);
+        assert_eq!(header, ["Test ID", "Acephate", "Acephate Qualifier", "Run"]);
+        assert_eq!(rows[0][1..3], [DataType::Float(10.0), cell("BelowLoq")]);
+        assert_eq!(rows[1][1..3], [DataType::Float(2.0), DataType::Empty]);
+        // left as it is
+        assert_eq!(rows[2][1..3], [cell("NA"), DataType::Empty]);
+        assert_eq!(rows[2][3], cell("A"));
+    }
+}
@@ -0,0 +1,171 @@
+use calamine::DataType;
+
+/// Where the unit of an extractor's analyte values is read from.
+pub enum UnitSource {
+    /// An output column holding the unit for each row, e.g. "Unit".
+    Column(&'static str),
+    /// Every value is reported in the same unit.
+    Fixed(&'static str),
+}
+
+pub struct UnitSpec {
+    pub source: UnitSource,
+    /// Unit that every analyte value is converted into.
+    pub canonical: &'static str,
+}
+
+#[derive(PartialEq)]
+enum Dimension {
+    MassFraction,
+    Count,
+}
+
+/// Look up the dimension of a unit and its power of ten relative to the dimension's base unit.
+fn unit_exponent(unit: &str) -> Option<(Dimension, i32)> {
+    let unit = unit
+        .trim()
+        .to_lowercase()
+        // micro sign and greek mu are both used for "micro"
+        .replace(['\u{b5}', '\u{3bc}'], "u");
+    match unit.as_str() {
+        "%" => Some((Dimension::MassFraction, -2)),
+        "mg/g" => Some((Dimension::MassFraction, -3)),
+        "ppm" | "ug/g" | "mg/kg" => Some((Dimension::MassFraction, -6)),
+        "ppb" | "ng/g" | "ug/kg" => Some((Dimension::MassFraction, -9)),
+        "cfu/g" => Some((Dimension::Count, 0)),
+        _ => None,
+    }
+}
+
+/// Conversion factor from `from` to `to`, if both units are known and share a dimension.
+pub fn conversion_factor(from: &str, to: &str) -> Option<f64> {
+    let (from_dim, from_exp) = unit_exponent(from)?;
+    let (to_dim, to_exp) = unit_exponent(to)?;
+    if from_dim == to_dim {
+        Some(10f64.powi(from_exp - to_exp))
+    } else {
+        None
+    }
+}
+
+pub fn numeric_value(dt: &DataType) -> Option<f64> {
+    match dt {
+        DataType::Float(f) => Some(*f),
+        DataType::Int(i) => Some(*i as f64),
+        DataType::String(s) => s.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+/// Convert the analyte result and limit columns of every row into the canonical unit of `spec`.
+///
+/// A "Normalized Unit" column is appended describing the unit the row's values are now in.
+/// Rows whose unit is unknown are left unconverted and keep their original unit.
+pub fn normalize_units(
+    header: &mut Vec<String>,
+    rows: &mut [Vec<DataType>],
+    columns: &[String],
+    spec: &UnitSpec,
+) {
+    let unit_idx = match spec.source {
+        UnitSource::Column(name) => header.iter().position(|h| h == name),
+        UnitSource::Fixed(_) => None,
+    };
+    let analyte_idxs: Vec<usize> = columns
+        .iter()
+        .filter_map(|c| header.iter().position(|h| h == c))
+        .collect();
+
+    for row in rows.iter_mut() {
+        let unit = match (&spec.source, unit_idx) {
+            (UnitSource::Fixed(unit), _) => Some(unit.to_string()),
+            (UnitSource::Column(_), Some(i)) => match &row[i] {
+                DataType::String(s) => Some(s.clone()),
+                _ => None,
+            },
+            (UnitSource::Column(_), None) => None,
+        };
+        let factor = unit
+            .as_ref()
+            .and_then(|u| conversion_factor(u, spec.canonical));
+
+        match factor {
+            Some(factor) => {
+                for i in analyte_idxs.iter() {
+                    if let Some(val) = numeric_value(&row[*i]) {
+                        // dividing keeps e.g. 12 mg/g at 1.2 % instead of 1.2000000000000002
+                        let val = if factor < 1.0 { val / factor.recip() } else { val * factor };
+                        row[*i] = DataType::Float(val);
+                    }
+                }
+                row.push(DataType::String(spec.canonical.to_string()));
+            }
+            None => row.push(unit.map_or(DataType::Empty, DataType::String)),
+        }
+    }
+    header.push("Normalized Unit".to_string());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn approx_eq(a: Option<f64>, b: f64) -> bool {
+        a.is_some_and(|a| (a - b).abs() < 1e-12 * b.abs().max(1.0))
+    }
+
+    #[test]
+    fn conversion_factors() {
+        assert!(approx_eq(conversion_factor("%", "mg/g"), 10.0));
+        assert!(approx_eq(conversion_factor("mg/g", "%"), 0.1));
+        assert!(approx_eq(conversion_factor("ppb", "ppm"), 0.001));
+        assert!(approx_eq(conversion_factor("\u{b5}g/g", "ppm"), 1.0));
+        assert!(approx_eq(conversion_factor(" UG/KG ", "ppb"), 1.0));
+        assert!(approx_eq(conversion_factor("CFU/g", "cfu/g"), 1.0));
+        assert_eq!(conversion_factor("%", "CFU/g"), None);
+        assert_eq!(conversion_factor("NA", "%"), None);
+    }
+
+    #[test]
+    fn values_are_converted_by_row_unit() {
+        let mut header: Vec<String> = ["Unit", "CBDa", "CBDa LLOQ", "Batch"]
+            .iter()
+            .map(|h| h.to_string())
+            .collect();
+        let unit = |u: &str| DataType::String(u.to_string());
+        let mut rows = vec![
+            vec![unit("mg/g"), DataType::Float(5.0), DataType::Float(0.1), unit("A")],
+            vec![unit("%"), DataType::Float(0.5), DataType::Empty, unit("A")],
+            vec![unit("NA"), DataType::Float(5.0), DataType::Float(0.1), unit("A")],
+        ];
+        let spec = UnitSpec {
+            source: UnitSource::Column("Unit"),
+            canonical: "%",
+        };
+        let columns = ["CBDa".to_string(), "CBDa LLOQ".to_string()];
+        normalize_units(&mut header, &mut rows, &columns, &spec);
+
+        assert_eq!(header.last().map(String::as_str), Some("Normalized Unit"));
+        assert!(approx_eq(numeric_value(&rows[0][1]), 0.5));
+        assert!(approx_eq(numeric_value(&rows[0][2]), 0.01));
+        assert_eq!(rows[0][4], unit("%"));
+        assert!(approx_eq(numeric_value(&rows[1][1]), 0.5));
+        assert_eq!(rows[1][2], DataType::Empty);
+        // an unknown unit is left as it is
+        assert_eq!(rows[2][1], DataType::Float(5.0));
+        assert_eq!(rows[2][4], unit("NA"));
+        assert_eq!(rows[0][3], unit("A"));
+    }
+
+    #[test]
+    fn fixed_unit_applies_to_every_row() {
+        let mut header = vec!["Reported CFU/g".to_string()];
+        let mut rows = vec![vec![DataType::Int(1200)]];
+        let spec = UnitSpec {
+            source: UnitSource::Fixed("CFU/g"),
+            canonical: "CFU/g",
+        };
+        normalize_units(&mut header, &mut rows, &["Reported CFU/g".to_string()], &spec);
+        assert_eq!(rows[0], [DataType::Float(1200.0), DataType::String("CFU/g".to_string())]);
+    }
+}
@@ -0,0 +1,104 @@
+//! Reading fields back out of CSV lines and hashing file contents.
+
+use std::borrow::Cow;
+
+/// The fields of a line of CSV, unquoted, with a doubled `""` inside a quoted field read as one
+/// quote.
+///
+/// Whitespace around a quoted field is dropped; an unquoted field is returned as written.
+pub fn csv_fields(line: &str) -> CsvFields<'_> {
+    CsvFields { rest: Some(line) }
+}
+
+/// Field `idx` of a line of CSV, read as `csv_fields` reads it.
+pub fn csv_field(line: &str, idx: usize) -> Option<Cow<'_, str>> {
+    csv_fields(line).nth(idx)
+}
+
+pub struct CsvFields<'a> {
+    /// The part of the line after the fields already returned, `None` after the last.
+    rest: Option<&'a str>,
+}
+
+impl<'a> Iterator for CsvFields<'a> {
+    type Item = Cow<'a, str>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let line = self.rest?;
+        // an escaped quote toggles twice, leaving the field quoted
+        let mut in_quotes = false;
+        for (i, c) in line.char_indices() {
+            match c {
+                '"' => in_quotes = !in_quotes,
+                ',' if !in_quotes => {
+                    self.rest = Some(&line[i + 1..]);
+                    return Some(unquote(&line[..i]));
+                }
+                _ => (),
+            }
+        }
+        self.rest = None;
+        Some(unquote(line))
+    }
+}
+
+fn unquote(field: &str) -> Cow<'_, str> {
+    let trimmed = field.trim();
+    if trimmed.len() < 2 || !trimmed.starts_with('"') || !trimmed.ends_with('"') {
+        return Cow::Borrowed(field);
+    }
+    let inner = &trimmed[1..trimmed.len() - 1];
+    if inner.contains("\"\"") {
+        Cow::Owned(inner.replace("\"\"", "\""))
+    } else {
+        Cow::Borrowed(inner)
+    }
+}
+
+/// 64 bit FNV-1a hash of `bytes`.
+pub fn hash_bytes(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in bytes.iter() {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fields() {
+        let line = "\"T-1\",12,,\"a, b\"";
+        assert_eq!(csv_fields(line).collect::<Vec<_>>(), ["T-1", "12", "", "a, b"]);
+        assert_eq!(csv_field(line, 3).as_deref(), Some("a, b"));
+        assert_eq!(csv_field(line, 4), None);
+        assert_eq!(csv_fields("").collect::<Vec<_>>(), [""]);
+    }
+
+    #[test]
+    fn escaped_quotes() {
+        let line = r#""6"" plate","say ""hi"", then go","""",x"#;
+        assert_eq!(
+            csv_fields(line).collect::<Vec<_>>(),
+            ["6\" plate", "say \"hi\", then go", "\"", "x"]
+        );
+        // no escape, nothing to copy
+        assert!(matches!(csv_field("\"a\",b", 0), Some(Cow::Borrowed("a"))));
+    }
+
+    #[test]
+    fn whitespace_around_quotes() {
+        assert_eq!(csv_fields(r#" "co" ,  x "#).collect::<Vec<_>>(), ["co", "  x "]);
+    }
+
+    #[test]
+    fn hashes() {
+        // FNV-1a test vectors
+        assert_eq!(hash_bytes(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(hash_bytes(b"a"), 0xaf63_dc4c_8601_ec8c);
+        assert_ne!(hash_bytes(b"v1"), hash_bytes(b"v2"));
+    }
+}